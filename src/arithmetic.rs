@@ -0,0 +1,374 @@
+// `$((...))` と `((...))` の整数の式の計算
+// 演算子の優先順位と結合の向きは C と同じ (`**` は単項演算子の次に強い)
+
+use super::structures::*;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// 長いものから順に試す
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+const ASSIGN_OPERATORS: &[&str] = &[
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    // 演算子は `=` や `+=` など
+    Assign(String, &'static str, Box<Expr>),
+    // (変数名, 増やす量, 前置か)
+    Increment(String, i64, bool),
+}
+
+// 式を計算する (変数の代入はシェル変数に反映される)
+pub fn eval(shell: &mut Shell, expr: &str) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let e = parser.comma()?;
+    if let Some(t) = parser.peek() {
+        return Err(format!(
+            "`{}`: syntax error near `{}`.",
+            expr.trim(),
+            describe(t)
+        ));
+    }
+    evaluate(shell, &e)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => n.to_string(),
+        Token::Name(name) => name.clone(),
+        Token::Op(op) => op.to_string(),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    rest = &rest[op.len()..];
+                }
+                None => return Err(format!("`{}`: invalid character in expression.", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// `10`, `0x1f`, `017` (8進数), `2#1010` (基数を指定) の形の数
+fn parse_number(s: &str) -> Result<i64, String> {
+    let (radix, digits) = if let Some(i) = s.find('#') {
+        let radix = s[..i].parse().ok().filter(|r| (2..=36).contains(r));
+        match radix {
+            Some(radix) => (radix, &s[i + 1..]),
+            None => return Err(format!("`{}`: invalid base.", s)),
+        }
+    } else if s.starts_with("0x") || s.starts_with("0X") {
+        (16, &s[2..])
+    } else if s.len() > 1 && s.starts_with('0') {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    i64::from_str_radix(digits, radix).map_err(|_| format!("`{}`: invalid number.", s))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("`{}` expected in expression.", op))
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, String> {
+        let mut e = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            e = Expr::Binary(",", Box::new(e), Box::new(self.assignment()?));
+        }
+        Ok(e)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if ASSIGN_OPERATORS.contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                return Ok(Expr::Assign(name, op, Box::new(self.assignment()?)));
+            }
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let cond = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    // 優先順位が min 以上の二項演算子の式
+    fn binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek_op() {
+            let prec = match precedence(op) {
+                Some(prec) if prec >= min => prec,
+                _ => break,
+            };
+            self.pos += 1;
+            // `**` だけは右結合
+            let next = if op == "**" { prec } else { prec + 1 };
+            let rhs = self.binary(next)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op @ "++") | Some(op @ "--") => {
+                self.pos += 1;
+                match self.peek() {
+                    Some(Token::Name(name)) => {
+                        let name = name.clone();
+                        self.pos += 1;
+                        let delta = if op == "++" { 1 } else { -1 };
+                        Ok(Expr::Increment(name, delta, true))
+                    }
+                    _ => Err(format!("`{}`: operand must be a variable.", op)),
+                }
+            }
+            Some(op @ "+") | Some(op @ "-") | Some(op @ "!") | Some(op @ "~") => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => match self.peek_op() {
+                Some(op @ "++") | Some(op @ "--") => {
+                    self.pos += 1;
+                    let delta = if op == "++" { 1 } else { -1 };
+                    Ok(Expr::Increment(name, delta, false))
+                }
+                _ => Ok(Expr::Variable(name)),
+            },
+            Some(Token::Op("(")) => {
+                let e = self.comma()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(t) => Err(format!(
+                "syntax error near `{}` in expression.",
+                describe(&t)
+            )),
+            None => Err("unexpected end of expression.".to_string()),
+        }
+    }
+}
+
+// 二項演算子の優先順位 (大きいほど強い)
+fn precedence(op: &str) -> Option<u8> {
+    let prec = match op {
+        "||" => 0,
+        "&&" => 1,
+        "|" => 2,
+        "^" => 3,
+        "&" => 4,
+        "==" | "!=" => 5,
+        "<" | "<=" | ">" | ">=" => 6,
+        "<<" | ">>" => 7,
+        "+" | "-" => 8,
+        "*" | "/" | "%" => 9,
+        "**" => 10,
+        _ => return None,
+    };
+    Some(prec)
+}
+
+fn evaluate(shell: &mut Shell, e: &Expr) -> Result<i64, String> {
+    match e {
+        Expr::Number(n) => Ok(*n),
+        Expr::Variable(name) => variable(shell, name),
+        Expr::Unary(op, e) => {
+            let n = evaluate(shell, e)?;
+            Ok(match *op {
+                "-" => n.wrapping_neg(),
+                "!" => (n == 0) as i64,
+                "~" => !n,
+                _ => n,
+            })
+        }
+        // 論理演算子と `,` は左辺を先に計算し、必要な場合だけ右辺を計算する
+        Expr::Binary("&&", lhs, rhs) => {
+            Ok((evaluate(shell, lhs)? != 0 && evaluate(shell, rhs)? != 0) as i64)
+        }
+        Expr::Binary("||", lhs, rhs) => {
+            Ok((evaluate(shell, lhs)? != 0 || evaluate(shell, rhs)? != 0) as i64)
+        }
+        Expr::Binary(",", lhs, rhs) => {
+            evaluate(shell, lhs)?;
+            evaluate(shell, rhs)
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let a = evaluate(shell, lhs)?;
+            let b = evaluate(shell, rhs)?;
+            apply(op, a, b)
+        }
+        Expr::Conditional(cond, then, otherwise) => {
+            if evaluate(shell, cond)? != 0 {
+                evaluate(shell, then)
+            } else {
+                evaluate(shell, otherwise)
+            }
+        }
+        Expr::Assign(name, op, e) => {
+            let value = evaluate(shell, e)?;
+            let value = match *op {
+                "=" => value,
+                // `+=` なら `+` で計算する
+                op => apply(&op[..op.len() - 1], variable(shell, name)?, value)?,
+            };
+            shell.assign_variable(name.clone(), value.to_string());
+            Ok(value)
+        }
+        Expr::Increment(name, delta, prefix) => {
+            let old = variable(shell, name)?;
+            let new = old.wrapping_add(*delta);
+            shell.assign_variable(name.clone(), new.to_string());
+            Ok(if *prefix { new } else { old })
+        }
+    }
+}
+
+fn apply(op: &str, a: i64, b: i64) -> Result<i64, String> {
+    let n = match op {
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" | "%" if b == 0 => return Err("division by zero.".to_string()),
+        "/" => a.wrapping_div(b),
+        "%" => a.wrapping_rem(b),
+        "**" if b < 0 => return Err("exponent less than 0.".to_string()),
+        "**" => power(a, b),
+        "<<" => a.wrapping_shl(b as u32),
+        ">>" => a.wrapping_shr(b as u32),
+        "<" => (a < b) as i64,
+        "<=" => (a <= b) as i64,
+        ">" => (a > b) as i64,
+        ">=" => (a >= b) as i64,
+        "==" => (a == b) as i64,
+        "!=" => (a != b) as i64,
+        "&" => a & b,
+        "^" => a ^ b,
+        "|" => a | b,
+        _ => return Err(format!("`{}`: unknown operator.", op)),
+    };
+    Ok(n)
+}
+
+// 二乗を繰り返してべき乗を計算する (大きな指数でも指数の桁数分の回数で済む)
+fn power(mut base: i64, mut exp: i64) -> i64 {
+    let mut n = 1i64;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            n = n.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    n
+}
+
+// 変数の値を数として読む (未設定や空なら 0)
+fn variable(shell: &Shell, name: &str) -> Result<i64, String> {
+    let value = std::env::var(name)
+        .ok()
+        .or_else(|| shell.get_variable(name))
+        .unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    match parse_number(digits) {
+        Ok(n) if negative => Ok(n.wrapping_neg()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("`{}`: `{}` is not a number.", name, value)),
+    }
+}
@@ -1,8 +1,14 @@
+use nix::poll;
+use nix::sys::signal;
+use nix::sys::termios;
 use nix::unistd;
 
 use super::execute;
+use super::printf;
 use super::structures;
 use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::io::RawFd;
 use std::path;
 
 /// aliasを追加
@@ -70,10 +76,45 @@ pub fn cd(_: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
     }
 }
 
+/// $PATH で見つけたコマンドのパスの記憶を操作する
+/// `hash` と `hash -l` は一覧を表示し、`hash -r` は全て忘れる
+/// `hash name...` は name を探して覚えておく
+pub fn hash(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let mut names = Vec::new();
+    let mut list = argv.is_empty();
+    for arg in argv {
+        match arg.as_str() {
+            "-r" => shell.forget_commands(),
+            "-l" => list = true,
+            _ if arg.starts_with('-') => {
+                eprintln!(
+                    "hash: `{}`: unknown option. usage: `$ hash [-lr] [name...]`",
+                    arg
+                );
+                return 1;
+            }
+            _ => names.push(arg),
+        }
+    }
+    let mut status = 0;
+    for name in names {
+        if shell.search_path(&name).is_none() {
+            eprintln!("hash: `{}` not found.", name);
+            status = 1;
+        }
+    }
+    if list {
+        for (name, path) in shell.hashed_commands() {
+            println!("{}\t{}", name, path.display());
+        }
+    }
+    status
+}
+
 /// コマンド名の実体を調べる
 pub fn type_(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
     for name in argv {
-        match shell.command_table.get(name.as_str()) {
+        match shell.find_command(name.as_str()) {
             Some(cmd) => match cmd {
                 structures::CommandType::Builtin(_) => {
                     println!("`{}` is a builtin function.", &name)
@@ -82,6 +123,9 @@ pub fn type_(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitC
                 structures::CommandType::Alias(alias) => {
                     println!("`{}` is an alias of `{}`", &name, alias)
                 }
+                structures::CommandType::Function(body) => {
+                    println!("`{}` is a function.\n{}() {}", &name, &name, body)
+                }
             },
             None => {
                 println!("`{}` not found.", &name);
@@ -110,25 +154,59 @@ pub fn echo(_: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
 }
 
 /// シェルを終了する
-pub fn exit(_: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
-    println!("good bye.");
-    std::process::exit(0);
+/// 引数で終了コードを指定できる (省略した場合は直前の終了コード)
+pub fn exit(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let exit_code = match argv.first().map(|a| a.parse::<execute::ExitCode>()) {
+        None => shell.last_status,
+        Some(Ok(code)) => code,
+        Some(Err(_)) => {
+            eprintln!("exit: numeric argument required.");
+            return 2;
+        }
+    };
+    if shell.interactive {
+        println!("good bye.");
+    }
+    std::process::exit(exit_code);
 }
 
 /// 環境変数を設定
-pub fn export(_: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
-    let usage = "usage: `$ export env_var_name = content`";
-    if argv.len() != 3 || argv[1].as_str() != "=" {
+/// POSIX と同じ `export name=content ...` の形でも書ける
+/// `export name` ではシェル変数を環境変数にする
+pub fn export(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
+    let usage = "usage: `$ export env_var_name = content` or `$ export name[=content]...`";
+    if argv.len() == 3 && argv[1].as_str() == "=" {
+        let name = argv.remove(0);
+        let _ = argv.remove(0);
+        let body = argv.remove(0);
+        std::env::set_var(name, body);
+        return 0;
+    }
+    if argv.is_empty() || argv.contains(&"=".to_string()) {
         eprintln!("export: mismatch arguments. {}", usage);
         return 1;
     }
-    let name = argv.remove(0);
-    let _ = argv.remove(0);
-    let body = argv.remove(0);
-    std::env::set_var(name, body);
+    for arg in argv {
+        match split_assignment(&arg) {
+            (name, Some(body)) => std::env::set_var(name, body),
+            (name, None) => {
+                if let Some(body) = shell.get_variable(name) {
+                    std::env::set_var(name, body);
+                }
+            }
+        }
+    }
     0
 }
 
+// `name=content` を名前と値に分ける (`=` が無ければ値は None)
+fn split_assignment(arg: &str) -> (&str, Option<&str>) {
+    match arg.find('=') {
+        Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+        None => (arg, None),
+    }
+}
+
 /// シェル変数を設定する
 pub fn var(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
     let usage = "usage: `$ var var_name = content`";
@@ -139,7 +217,44 @@ pub fn var(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::Exi
     let name = argv.remove(0);
     let _ = argv.remove(0);
     let body = argv.remove(0);
-    shell.variables.insert(name, body);
+    shell.set_variable(name, body);
+    0
+}
+
+/// 関数の中だけで使うシェル変数を宣言する
+/// `local var_name = content` (POSIX モードのシェルと同じ `local var_name=content`) で値も設定できる
+pub fn local(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
+    let usage = "usage: `$ local var_name [= content]`";
+    if !shell.in_function {
+        eprintln!("local: can only be used in a function.");
+        return 1;
+    }
+    let value = match argv.len() {
+        1 => match split_assignment(&argv[0]) {
+            (name, Some(value)) => {
+                let value = value.to_string();
+                argv[0] = name.to_string();
+                Some(value)
+            }
+            (_, None) => None,
+        },
+        3 if argv[1].as_str() == "=" => argv.pop(),
+        _ => {
+            eprintln!("local: mismatch arguments. {}", usage);
+            return 1;
+        }
+    };
+    let name = argv.remove(0);
+    shell.locals.insert(name.clone());
+    shell.unset_globals.remove(&name);
+    match value {
+        Some(value) => {
+            shell.variables.insert(name, value);
+        }
+        None => {
+            shell.variables.remove(&name);
+        }
+    }
     0
 }
 
@@ -152,41 +267,865 @@ pub fn unset(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::E
     }
     let name = argv.remove(0);
     std::env::remove_var(&name);
-    shell.variables.remove(&name);
+    shell.unset_variable(&name);
     0
 }
 
-/// command_tableを再設定する
-pub fn reload_path(shell: &mut structures::Shell, _: Vec<String>) -> execute::ExitCode {
-    let mut command_table: HashMap<String, structures::CommandType> = HashMap::new();
-    // PATH からコマンドパスのテーブルを構築
-    let path_str = match std::env::var("PATH") {
-        Ok(s) => s,
-        Err(_) => "".to_string(),
-    };
-
-    // println!("{}", path_str);
-    for dir in path_str.split(":").map(|p| path::Path::new(p)) {
-        if !dir.exists() {
-            // eprintln!("path `{}` doesn't exist. skipped.", dir.display());
-            continue;
-        }
-        if !dir.is_dir() {
-            // eprintln!("`{}` is not a directory. skipped.", dir.display());
-            continue;
-        }
-
-        for entry in dir.read_dir().unwrap() {
-            if let Ok(ent) = entry {
-                if let Some(name) = ent.path().file_name() {
-                    command_table.insert(
-                        name.to_str().unwrap().to_string(),
-                        structures::CommandType::External(ent.path().clone()),
-                    );
+/// シェルのオプションを切り替える
+/// `set -o name` で有効に、`set +o name` で無効にする
+/// 引数を指定しない場合は現在の設定を表示する
+/// - failglob: パス名展開で一致するものが無ければエラーにする
+/// - nullglob: パス名展開で一致するものが無ければ単語を取り除く
+pub fn set(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let usage = "usage: `$ set [-o|+o option_name]...`";
+    if argv.is_empty() || argv == ["-o"] {
+        for (name, enabled) in shell.options.list() {
+            println!("{:<15} {}", name, if enabled { "on" } else { "off" });
+        }
+        return 0;
+    }
+
+    let mut args = argv.iter();
+    while let Some(flag) = args.next() {
+        let enable = match flag.as_str() {
+            "-o" => true,
+            "+o" => false,
+            _ => {
+                eprintln!("set: unknown flag `{}`. {}", flag, usage);
+                return 2;
+            }
+        };
+        match args.next() {
+            Some(name) => {
+                if !shell.options.set(name, enable) {
+                    eprintln!("set: unknown option `{}`.", name);
+                    return 1;
+                }
+            }
+            None => {
+                eprintln!("set: option name required. {}", usage);
+                return 2;
+            }
+        }
+    }
+    0
+}
+
+/// コマンドの引数の補完方法を登録する
+/// - `complete -W "word..." command...`: 単語の並びから補完する
+/// - `complete -F function command...`: 関数の出力の各行から補完する
+/// - `complete -C command command...`: コマンドの出力の各行から補完する
+/// 関数とコマンドには (コマンド名, 補完する単語, 直前の単語) が引数として渡される
+/// `complete -r command...` で登録を削除し、引数を指定しない場合は一覧を表示する
+pub fn complete(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let usage = "usage: `$ complete [-W words | -F function | -C command | -r] command...`";
+    if argv.is_empty() {
+        let mut names: Vec<&String> = shell.completions.keys().collect();
+        names.sort();
+        for name in names {
+            match &shell.completions[name] {
+                structures::CompletionSpec::Words(words) => {
+                    println!("complete -W \"{}\" {}", words.join(" "), name)
                 }
+                structures::CompletionSpec::Function(f) => println!("complete -F {} {}", f, name),
+                structures::CompletionSpec::Command(c) => println!("complete -C {} {}", c, name),
+            }
+        }
+        return 0;
+    }
+
+    let (spec, commands) = match argv[0].as_str() {
+        "-r" => (None, &argv[1..]),
+        flag @ "-W" | flag @ "-F" | flag @ "-C" if argv.len() >= 2 => {
+            let arg = argv[1].clone();
+            let spec = match flag {
+                "-W" => structures::CompletionSpec::Words(
+                    arg.split_whitespace().map(str::to_string).collect(),
+                ),
+                "-F" => structures::CompletionSpec::Function(arg),
+                _ => structures::CompletionSpec::Command(arg),
+            };
+            (Some(spec), &argv[2..])
+        }
+        _ => {
+            eprintln!("complete: mismatch arguments. {}", usage);
+            return 2;
+        }
+    };
+    if commands.is_empty() {
+        eprintln!("complete: command name required. {}", usage);
+        return 2;
+    }
+    for name in commands {
+        match &spec {
+            Some(spec) => {
+                shell.completions.insert(name.clone(), spec.clone());
+            }
+            None => {
+                shell.completions.remove(name);
             }
         }
     }
+    0
+}
+
+/// 1行読み込み、$IFS の文字で区切ってシェル変数に設定する
+/// 変数が足りなければ残りは全て最後の変数に、変数を指定しなければそのまま REPLY に設定する
+/// 入力の終わりに達したら 1 を返すので `while read line` のループは終わる
+/// - -r: `\` をエスケープとして扱わない
+/// - -s: 入力した文字を表示しない (端末から読む場合)
+/// - -a array: 区切ったものを配列に設定する
+/// - -d delim: 改行の代わりに delim の最初の文字まで読む
+/// - -n count: count 文字読んだら終わる
+/// - -p prompt: 端末から読む場合は先に prompt を表示する
+/// - -t timeout: timeout 秒以内に読み終わらなければ 142 を返す
+/// - -u fd: 標準入力の代わりに fd から読む
+pub fn read(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let usage = "usage: `$ read [-rs] [-a array] [-d delim] [-n count] [-p prompt] [-t timeout] [-u fd] [name...]`";
+    let mut opts = ReadOptions {
+        raw: false,
+        delimiter: b'\n',
+        count: None,
+        timeout: None,
+        fd: 0,
+    };
+    let (mut silent, mut prompt, mut array) = (false, None, None);
+    let mut args = argv.into_iter().peekable();
+    while let Some(arg) = args.peek() {
+        if arg == "--" {
+            args.next();
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            break;
+        }
+        let arg = args.next().unwrap();
+        let mut flags = arg[1..].chars();
+        while let Some(flag) = flags.next() {
+            match flag {
+                'r' => opts.raw = true,
+                's' => silent = true,
+                'a' | 'd' | 'n' | 'p' | 't' | 'u' => {
+                    // 値は同じ引数の残りか次の引数
+                    let rest: String = flags.by_ref().collect();
+                    let value = match if rest.is_empty() {
+                        args.next()
+                    } else {
+                        Some(rest)
+                    } {
+                        Some(value) => value,
+                        None => {
+                            eprintln!("read: `-{}` requires an argument. {}", flag, usage);
+                            return 2;
+                        }
+                    };
+                    let invalid = || {
+                        eprintln!("read: `{}`: invalid argument for `-{}`.", value, flag);
+                        2
+                    };
+                    match flag {
+                        'a' => array = Some(value.clone()),
+                        // 空なら NUL 文字まで読む
+                        'd' => opts.delimiter = value.bytes().next().unwrap_or(0),
+                        'p' => prompt = Some(value.clone()),
+                        'n' => match value.parse() {
+                            Ok(n) => opts.count = Some(n),
+                            Err(_) => return invalid(),
+                        },
+                        't' => match value
+                            .parse::<f64>()
+                            .map(std::time::Duration::try_from_secs_f64)
+                        {
+                            Ok(Ok(t)) => opts.timeout = Some(t),
+                            _ => return invalid(),
+                        },
+                        _ => match value.parse() {
+                            Ok(fd) => opts.fd = fd,
+                            Err(_) => return invalid(),
+                        },
+                    }
+                }
+                _ => {
+                    eprintln!("read: `-{}`: unknown option. {}", flag, usage);
+                    return 2;
+                }
+            }
+        }
+    }
+    let names: Vec<String> = args.collect();
+    for name in names.iter().chain(array.iter()) {
+        if !is_identifier(name) {
+            eprintln!("read: `{}`: invalid variable name.", name);
+            return 2;
+        }
+    }
+
+    // `-t 0` は読み込まずに入力があるかだけ調べる
+    if opts.timeout == Some(std::time::Duration::from_secs(0)) {
+        let mut fds = [poll::PollFd::new(opts.fd, poll::PollFlags::POLLIN)];
+        return match poll::poll(&mut fds, 0) {
+            Ok(n) if n > 0 => 0,
+            _ => 1,
+        };
+    }
+
+    let tty = unistd::isatty(opts.fd).unwrap_or(false);
+    if let Some(prompt) = prompt.filter(|_| tty) {
+        eprint!("{}", prompt);
+        let _ = std::io::stderr().flush();
+    }
+    let saved = if silent && tty {
+        termios::tcgetattr(opts.fd).ok()
+    } else {
+        None
+    };
+    if let Some(original) = &saved {
+        let mut quiet = original.clone();
+        quiet.local_flags.remove(termios::LocalFlags::ECHO);
+        let _ = termios::tcsetattr(opts.fd, termios::SetArg::TCSADRAIN, &quiet);
+    }
+    let result = read_input(&opts);
+    if let Some(original) = saved {
+        let _ = termios::tcsetattr(opts.fd, termios::SetArg::TCSADRAIN, &original);
+    }
+    let (chars, status) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("read: {}", e);
+            return 1;
+        }
+    };
+
+    let ifs = super::word::ifs(shell);
+    if let Some(array) = array {
+        shell.set_array(array, split_fields(&chars, &ifs, None));
+    } else if names.is_empty() {
+        let line = chars.into_iter().map(|(c, _)| c).collect();
+        shell.assign_variable("REPLY".to_string(), line);
+    } else {
+        let mut fields = split_fields(&chars, &ifs, Some(names.len())).into_iter();
+        for name in names {
+            shell.assign_variable(name, fields.next().unwrap_or_default());
+        }
+    }
+    status
+}
+
+struct ReadOptions {
+    raw: bool,
+    delimiter: u8,
+    count: Option<usize>,
+    timeout: Option<std::time::Duration>,
+    fd: RawFd,
+}
+
+enum ReadByte {
+    Byte(u8),
+    Eof,
+    Timeout,
+}
+
+// 区切り文字まで読み込み、(文字, `\` でエスケープされたか) の並びと終了コードを返す
+// 後に続く入力を残しておくため、1バイトずつ読む
+fn read_input(opts: &ReadOptions) -> Result<(Vec<(char, bool)>, execute::ExitCode), String> {
+    // 表せないほど先の期限は期限なしと同じ
+    let deadline = opts
+        .timeout
+        .and_then(|t| std::time::Instant::now().checked_add(t));
+    let next_byte = || -> Result<ReadByte, String> {
+        if let Some(deadline) = deadline {
+            let mut fds = [poll::PollFd::new(opts.fd, poll::PollFlags::POLLIN)];
+            loop {
+                let left = deadline.saturating_duration_since(std::time::Instant::now());
+                // poll で一度に待てる時間には上限があるので、長い場合は分けて待つ
+                let millis = left.as_millis().min(i32::MAX as u128) as i32;
+                match poll::poll(&mut fds, millis) {
+                    Ok(0) if (millis as u128) < left.as_millis() => continue,
+                    Ok(0) => return Ok(ReadByte::Timeout),
+                    Ok(_) => break,
+                    Err(e) => return Err(format!("{}", e)),
+                }
+            }
+        }
+        let mut buf = [0u8];
+        match unistd::read(opts.fd, &mut buf) {
+            Ok(0) => Ok(ReadByte::Eof),
+            Ok(_) => Ok(ReadByte::Byte(buf[0])),
+            Err(e) => Err(format!("{}", e)),
+        }
+    };
+    // 1文字分のバイト列を読んで文字にする
+    let read_char = |first: u8| -> Result<char, String> {
+        let len = match first {
+            0xf0..=0xff => 4,
+            0xe0..=0xef => 3,
+            0xc0..=0xdf => 2,
+            _ => 1,
+        };
+        let mut bytes = vec![first];
+        while bytes.len() < len {
+            match next_byte()? {
+                ReadByte::Byte(b) => bytes.push(b),
+                _ => break,
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes)
+            .chars()
+            .next()
+            .unwrap_or('\u{fffd}'))
+    };
+
+    let mut chars = Vec::new();
+    let mut escaped = false;
+    loop {
+        if opts.count.is_some_and(|n| chars.len() >= n) {
+            return Ok((chars, 0));
+        }
+        let b = match next_byte()? {
+            ReadByte::Byte(b) => b,
+            ReadByte::Eof => return Ok((chars, 1)),
+            // 時間切れでも読めた分は設定する
+            ReadByte::Timeout => return Ok((chars, 142)),
+        };
+        if escaped {
+            escaped = false;
+            // `\` と改行は行の継続
+            if b != b'\n' {
+                chars.push((read_char(b)?, true));
+            }
+        } else if b == opts.delimiter {
+            return Ok((chars, 0));
+        } else if b == b'\\' && !opts.raw {
+            escaped = true;
+        } else {
+            chars.push((read_char(b)?, false));
+        }
+    }
+}
+
+// $IFS の文字で区切る (エスケープされた文字では区切らない)
+// max 個目の単語は行の残り全て (末尾の空白類の区切りは除く) になる
+fn split_fields(chars: &[(char, bool)], ifs: &str, max: Option<usize>) -> Vec<String> {
+    let is_sep = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let is_space = |x: &(char, bool)| is_sep(x) && x.0.is_whitespace();
+    let mut fields = Vec::new();
+    let mut i = 0;
+    while i < chars.len() && is_space(&chars[i]) {
+        i += 1;
+    }
+    while i < chars.len() {
+        if max == Some(fields.len() + 1) {
+            let mut end = chars.len();
+            while end > i && is_space(&chars[end - 1]) {
+                end -= 1;
+            }
+            fields.push(chars[i..end].iter().map(|&(c, _)| c).collect());
+            break;
+        }
+        let start = i;
+        while i < chars.len() && !is_sep(&chars[i]) {
+            i += 1;
+        }
+        fields.push(chars[start..i].iter().map(|&(c, _)| c).collect());
+        // 空白類の並びと、その間の空白類以外の区切り1つまでが1つの区切り
+        while i < chars.len() && is_space(&chars[i]) {
+            i += 1;
+        }
+        if i < chars.len() && is_sep(&chars[i]) {
+            i += 1;
+            while i < chars.len() && is_space(&chars[i]) {
+                i += 1;
+            }
+        }
+    }
+    fields
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// 条件式を評価し、真なら 0、偽なら 1、式が正しくなければ 2 を返す
+/// - ファイル: `-e`, `-f`, `-d`, `-r`, `-w`, `-x`, `-s`, `-L` など、`a -nt b`, `a -ot b`, `a -ef b`
+/// - 文字列: `-z s`, `-n s`, `a = b`, `a != b`, `a < b`, `a > b`
+/// - 整数: `a -eq b`, `-ne`, `-lt`, `-le`, `-gt`, `-ge`
+/// - `! e`, `e -a e`, `e -o e`, `( e )`
+pub fn test(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    match eval_test(shell, &argv) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("test: {}", e);
+            2
+        }
+    }
+}
+
+/// `[ 式 ]` (最後の引数は `]`)
+pub fn bracket(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
+    if argv.last().map(String::as_str) != Some("]") {
+        eprintln!("[: missing `]`.");
+        return 2;
+    }
+    argv.pop();
+    test(shell, argv)
+}
+
+// 引数の数が少ない場合は POSIX の規則で先に解釈を決める (`[ ! = x ]` は `!` と `x` の比較)
+fn eval_test(shell: &structures::Shell, args: &[String]) -> Result<bool, String> {
+    let a: Vec<&str> = args.iter().map(String::as_str).collect();
+    match a.as_slice() {
+        [] => return Ok(false),
+        [s] => return Ok(!s.is_empty()),
+        ["!", s] => return Ok(s.is_empty()),
+        [op, s] if op.starts_with('-') => return unary_test(shell, op, s),
+        [x, op, y] if is_binary_test(op) => return binary_test(op, x, y),
+        ["!", ..] if a.len() <= 4 => return eval_test(shell, &args[1..]).map(|b| !b),
+        ["(", s, ")"] => return Ok(!s.is_empty()),
+        ["(", _, _, ")"] => return eval_test(shell, &args[1..3]),
+        _ => {}
+    }
+    let mut parser = TestParser {
+        shell,
+        args: &a,
+        pos: 0,
+    };
+    let result = parser.or()?;
+    match a.get(parser.pos) {
+        Some(arg) => Err(format!("`{}`: unexpected argument.", arg)),
+        None => Ok(result),
+    }
+}
+
+fn is_binary_test(op: &str) -> bool {
+    [
+        "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+        "-a", "-o",
+    ]
+    .contains(&op)
+}
+
+// `-o` より `-a` が強く、`!` はさらに強い
+struct TestParser<'a> {
+    shell: &'a structures::Shell<'a>,
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> TestParser<'a> {
+    fn next_is(&self, arg: &str) -> bool {
+        self.args.get(self.pos) == Some(&arg)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.next_is("-o") {
+            self.pos += 1;
+            // 両辺とも評価して式の誤りを見つける
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.next_is("-a") {
+            self.pos += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.next_is("!") && self.pos + 1 < self.args.len() {
+            self.pos += 1;
+            return self.not().map(|b| !b);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let args = self.args;
+        let arg = match args.get(self.pos) {
+            Some(arg) => *arg,
+            None => return Err("argument expected.".to_string()),
+        };
+        if arg == "(" && self.pos + 1 < args.len() {
+            self.pos += 1;
+            let result = self.or()?;
+            if !self.next_is(")") {
+                return Err("`)` expected.".to_string());
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+        if let (Some(op), Some(rhs)) = (args.get(self.pos + 1), args.get(self.pos + 2)) {
+            if is_binary_test(op) && *op != "-a" && *op != "-o" {
+                self.pos += 3;
+                return binary_test(op, arg, rhs);
+            }
+        }
+        if arg.starts_with('-') && arg.len() == 2 {
+            if let Some(operand) = args.get(self.pos + 1) {
+                self.pos += 2;
+                return unary_test(self.shell, arg, operand);
+            }
+        }
+        self.pos += 1;
+        Ok(!arg.is_empty())
+    }
+}
+
+/// `-f file` などの単項の条件 (`test` と `[[ ... ]]` で使う)
+pub fn unary_test(shell: &structures::Shell, op: &str, arg: &str) -> Result<bool, String> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+    let meta = || std::fs::metadata(arg).ok();
+    let mode = |bit: u32| meta().is_some_and(|m| m.permissions().mode() & bit != 0);
+    let access = |flag| unistd::access(arg, flag).is_ok();
+    let result = match op {
+        "-z" => arg.is_empty(),
+        "-n" => !arg.is_empty(),
+        "-e" | "-a" => meta().is_some(),
+        "-f" => meta().is_some_and(|m| m.is_file()),
+        "-d" => meta().is_some_and(|m| m.is_dir()),
+        "-s" => meta().is_some_and(|m| m.len() > 0),
+        "-b" => meta().is_some_and(|m| m.file_type().is_block_device()),
+        "-c" => meta().is_some_and(|m| m.file_type().is_char_device()),
+        "-p" => meta().is_some_and(|m| m.file_type().is_fifo()),
+        "-S" => meta().is_some_and(|m| m.file_type().is_socket()),
+        "-L" | "-h" => std::fs::symlink_metadata(arg).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => access(unistd::AccessFlags::R_OK),
+        "-w" => access(unistd::AccessFlags::W_OK),
+        "-x" => access(unistd::AccessFlags::X_OK),
+        "-u" => mode(0o4000),
+        "-g" => mode(0o2000),
+        "-k" => mode(0o1000),
+        "-O" => meta().is_some_and(|m| m.uid() == unistd::geteuid().as_raw()),
+        "-G" => meta().is_some_and(|m| m.gid() == unistd::getegid().as_raw()),
+        "-t" => unistd::isatty(parse_integer(arg)? as RawFd).unwrap_or(false),
+        "-v" => std::env::var_os(arg).is_some() || shell.get_variable(arg).is_some(),
+        _ => return Err(format!("`{}`: unknown operator.", op)),
+    };
+    Ok(result)
+}
+
+/// `a = b` や `a -lt b` などの二項の条件 (`test` と `[[ ... ]]` で使う)
+pub fn binary_test(op: &str, a: &str, b: &str) -> Result<bool, String> {
+    use std::os::unix::fs::MetadataExt;
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let result = match op {
+        "=" | "==" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        ">" => a > b,
+        "-a" => !a.is_empty() && !b.is_empty(),
+        "-o" => !a.is_empty() || !b.is_empty(),
+        // 存在しないファイルはどのファイルよりも古いとみなす
+        "-nt" => match (modified(a), modified(b)) {
+            (Some(a), Some(b)) => a > b,
+            (a, b) => a.is_some() && b.is_none(),
+        },
+        "-ot" => match (modified(a), modified(b)) {
+            (Some(a), Some(b)) => a < b,
+            (a, b) => a.is_none() && b.is_some(),
+        },
+        "-ef" => match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        },
+        _ => {
+            let (a, b) = (parse_integer(a)?, parse_integer(b)?);
+            match op {
+                "-eq" => a == b,
+                "-ne" => a != b,
+                "-lt" => a < b,
+                "-le" => a <= b,
+                "-gt" => a > b,
+                "-ge" => a >= b,
+                _ => return Err(format!("`{}`: unknown operator.", op)),
+            }
+        }
+    };
+    Ok(result)
+}
+
+fn parse_integer(s: &str) -> Result<i64, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("`{}`: integer expression expected.", s))
+}
+
+/// 書式に従って引数を出力する
+/// `%s %d %i %u %x %X %o %f %e %g %c %b %q %%` と幅・精度・フラグ (`-+ 0#`) が使える
+/// 引数が余れば書式を繰り返し使い、`-v var` なら出力する代わりにシェル変数に設定する
+/// 数として読めない引数があれば 1 を返す
+/// `printf [-v var] format [args...]`
+pub fn printf(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
+    let usage = "usage: `$ printf [-v var] format [args...]`";
+    let mut var = None;
+    if argv.first().map(String::as_str) == Some("-v") {
+        if argv.len() < 2 || !is_identifier(&argv[1]) {
+            eprintln!("printf: variable name required. {}", usage);
+            return 2;
+        }
+        var = Some(argv.remove(1));
+        argv.remove(0);
+    }
+    if argv.first().map(String::as_str) == Some("--") {
+        argv.remove(0);
+    }
+    if argv.is_empty() {
+        eprintln!("printf: format required. {}", usage);
+        return 2;
+    }
+    let format = argv.remove(0);
+    let formatted = match printf::format(&format, &argv) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            eprintln!("printf: {}", e);
+            return 1;
+        }
+    };
+    for e in formatted.errors.iter() {
+        eprintln!("printf: {}", e);
+    }
+    match var {
+        Some(name) => {
+            let text = String::from_utf8_lossy(&formatted.text).into_owned();
+            shell.assign_variable(name, text)
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            if let Err(e) = stdout
+                .write_all(&formatted.text)
+                .and_then(|_| stdout.flush())
+            {
+                eprintln!("printf: {}", e);
+                return 1;
+            }
+        }
+    }
+    if formatted.errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// ジョブ指定 (`%n`, `%+`, `%-`, 省略時はカレントジョブ) からジョブテーブルの添字を求める
+fn find_job(shell: &structures::Shell, spec: Option<&String>) -> Result<usize, String> {
+    if shell.job_control.is_none() {
+        return Err("no job control.".to_string());
+    }
+    let len = shell.jobs.len();
+    let index = match spec.map(|s| s.as_str()) {
+        None | Some("%") | Some("%%") | Some("%+") => len.checked_sub(1),
+        Some("%-") => len.checked_sub(2),
+        Some(spec) => {
+            let id = spec
+                .trim_start_matches('%')
+                .parse::<usize>()
+                .map_err(|_| format!("`{}`: invalid job spec.", spec))?;
+            shell.jobs.iter().position(|j| j.id == id)
+        }
+    };
+    index.ok_or_else(|| "no such job.".to_string())
+}
+
+/// ジョブの一覧を表示する
+/// 終了したジョブは表示した後にジョブテーブルから取り除く
+pub fn jobs(shell: &mut structures::Shell, _: Vec<String>) -> execute::ExitCode {
+    if let Err(e) = shell.update_jobs() {
+        eprintln!("jobs: {}", e);
+        return 1;
+    }
+    for (i, job) in shell.jobs.iter().enumerate() {
+        println!("{}", shell.format_job(job, shell.job_mark(i)));
+    }
+    shell.remove_done_jobs();
+    0
+}
+
+/// ジョブをフォアグラウンドで再開する
+pub fn fg(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let index = match find_job(shell, argv.first()) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("fg: {}", e);
+            return 1;
+        }
+    };
+    let mut job = shell.jobs.remove(index);
+    println!("{}", job.command);
+    if let Err(e) = signal::killpg(job.pgid, signal::Signal::SIGCONT) {
+        eprintln!("fg: {}", e);
+        return 1;
+    }
+    job.mark_continued();
+    match shell.wait_foreground(job) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("fg: {}", e);
+            1
+        }
+    }
+}
+
+/// 停止しているジョブをバックグラウンドで再開する
+pub fn bg(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let index = match find_job(shell, argv.first()) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("bg: {}", e);
+            return 1;
+        }
+    };
+    let job = &mut shell.jobs[index];
+    if let Err(e) = signal::killpg(job.pgid, signal::Signal::SIGCONT) {
+        eprintln!("bg: {}", e);
+        return 1;
+    }
+    job.mark_continued();
+    println!("[{}]+ {} &", job.id, job.command);
+    0
+}
+
+/// ジョブをジョブテーブルから取り除く (プロセスはそのまま動かし続ける)
+pub fn disown(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    match find_job(shell, argv.first()) {
+        Ok(i) => {
+            shell.jobs.remove(i);
+            0
+        }
+        Err(e) => {
+            eprintln!("disown: {}", e);
+            1
+        }
+    }
+}
+
+/// 位置パラメータを左にずらす
+/// 引数でずらす数を指定できる (省略した場合は1)
+pub fn shift(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    let n = match argv.first().map(|a| a.parse::<usize>()) {
+        None => 1,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("shift: numeric argument required.");
+            return 1;
+        }
+    };
+    if n > shell.positional.len() {
+        eprintln!("shift: shift count out of range.");
+        return 1;
+    }
+    shell.positional.drain(..n);
+    0
+}
+
+// break と continue の引数 (抜けるループの数) を読む
+fn loop_count(shell: &structures::Shell, name: &str, argv: &[String]) -> Option<usize> {
+    if shell.loop_depth == 0 {
+        eprintln!("{}: only meaningful in a loop.", name);
+        return None;
+    }
+    match argv.first().map(|a| a.parse::<usize>()) {
+        None => Some(1),
+        Some(Ok(n)) if n > 0 => Some(n.min(shell.loop_depth)),
+        _ => {
+            eprintln!("{}: loop count out of range.", name);
+            None
+        }
+    }
+}
+
+/// ループを抜ける
+/// 引数で抜けるループの数を指定できる (省略した場合は1)
+pub fn break_(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    match loop_count(shell, "break", &argv) {
+        Some(n) => {
+            shell.flow = Some(structures::Flow::Break(n));
+            0
+        }
+        None => 1,
+    }
+}
+
+/// ループの次の繰り返しに移る
+/// 引数で数えるループの数を指定できる (省略した場合は1)
+pub fn continue_(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    match loop_count(shell, "continue", &argv) {
+        Some(n) => {
+            shell.flow = Some(structures::Flow::Continue(n));
+            0
+        }
+        None => 1,
+    }
+}
+
+/// 関数から戻る
+/// 引数で終了コードを指定できる (省略した場合は直前の終了コード)
+pub fn return_(shell: &mut structures::Shell, argv: Vec<String>) -> execute::ExitCode {
+    if !shell.in_function {
+        eprintln!("return: can only be used in a function.");
+        return 1;
+    }
+    let exit_code = match argv.first().map(|a| a.parse::<execute::ExitCode>()) {
+        None => shell.last_status,
+        Some(Ok(code)) => code,
+        Some(Err(_)) => {
+            eprintln!("return: numeric argument required.");
+            return 2;
+        }
+    };
+    shell.flow = Some(structures::Flow::Return);
+    exit_code
+}
+
+/// ファイルを読み込んで今のシェルで実行する (`.` も同じ)
+/// `/` を含まない名前は $PATH から探し、見つからなければカレントディレクトリから探す
+/// 引数を付けると、実行している間だけそれが位置パラメータになる
+/// `source file [args...]`
+pub fn source(shell: &mut structures::Shell, mut argv: Vec<String>) -> execute::ExitCode {
+    if argv.is_empty() {
+        eprintln!("source: filename argument required.");
+        return 2;
+    }
+    let name = argv.remove(0);
+    let path = if name.contains('/') {
+        path::PathBuf::from(&name)
+    } else {
+        let path_str = std::env::var("PATH").unwrap_or_default();
+        path_str
+            .split(':')
+            .map(|dir| path::Path::new(dir).join(&name))
+            .find(|p| p.is_file())
+            .unwrap_or_else(|| path::PathBuf::from(&name))
+    };
+
+    let saved = if argv.is_empty() {
+        None
+    } else {
+        Some(std::mem::replace(&mut shell.positional, argv))
+    };
+    let exit_code = match shell.run_file(&path) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("source: {}", e);
+            1
+        }
+    };
+    if let Some(positional) = saved {
+        shell.positional = positional;
+    }
+    exit_code
+}
+
+/// command_tableを再設定する
+/// エイリアスと関数はそのまま残し、$PATH で見つけたコマンドのパスは忘れる
+pub fn reload_path(shell: &mut structures::Shell, _: Vec<String>) -> execute::ExitCode {
+    let mut command_table: HashMap<String, structures::CommandType> = HashMap::new();
 
     // 組み込み関数の登録
     command_table.insert(
@@ -210,13 +1149,47 @@ pub fn reload_path(shell: &mut structures::Shell, _: Vec<String>) -> execute::Ex
     command_table.insert(format!("export"), structures::CommandType::Builtin(export));
     command_table.insert(format!("var"), structures::CommandType::Builtin(var));
     command_table.insert(format!("unset"), structures::CommandType::Builtin(unset));
+    command_table.insert(format!("set"), structures::CommandType::Builtin(set));
+    command_table.insert(
+        format!("complete"),
+        structures::CommandType::Builtin(complete),
+    );
+    command_table.insert(format!("shift"), structures::CommandType::Builtin(shift));
+    command_table.insert(format!("break"), structures::CommandType::Builtin(break_));
+    command_table.insert(
+        format!("continue"),
+        structures::CommandType::Builtin(continue_),
+    );
+    command_table.insert(format!("local"), structures::CommandType::Builtin(local));
+    command_table.insert(format!("return"), structures::CommandType::Builtin(return_));
+    command_table.insert(format!("source"), structures::CommandType::Builtin(source));
+    command_table.insert(format!("."), structures::CommandType::Builtin(source));
+
+    command_table.insert(format!("jobs"), structures::CommandType::Builtin(jobs));
+    command_table.insert(format!("fg"), structures::CommandType::Builtin(fg));
+    command_table.insert(format!("bg"), structures::CommandType::Builtin(bg));
+    command_table.insert(format!("disown"), structures::CommandType::Builtin(disown));
+    command_table.insert(format!("hash"), structures::CommandType::Builtin(hash));
+    command_table.insert(format!("read"), structures::CommandType::Builtin(read));
+    command_table.insert(format!("test"), structures::CommandType::Builtin(test));
+    command_table.insert(format!("["), structures::CommandType::Builtin(bracket));
+    command_table.insert(format!("printf"), structures::CommandType::Builtin(printf));
 
     command_table.insert(
         format!("reload-path"),
         structures::CommandType::Builtin(reload_path),
     );
 
+    for (name, cmd) in shell.command_table.drain() {
+        match cmd {
+            structures::CommandType::Alias(_) | structures::CommandType::Function(_) => {
+                command_table.insert(name, cmd);
+            }
+            _ => {}
+        }
+    }
     shell.command_table = command_table;
+    shell.forget_commands();
 
     0
-}
\ No newline at end of file
+}
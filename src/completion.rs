@@ -0,0 +1,297 @@
+// 行エディタの Tab キーによる補完
+// コマンド名、ファイルのパス、変数名と、`complete` で登録されたコマンドごとの候補を扱う
+
+use super::pattern;
+use super::structures::*;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "while", "until", "do", "done", "for", "case", "esac",
+];
+
+// 入力中の行を区切ったもの
+enum Token {
+    Word(String),
+    Operator(String),
+}
+
+// カーソルより前の行 (line) から補完する単語の開始位置 (文字数) と候補を求める
+pub fn complete(shell: &mut Shell, line: &str) -> (usize, Vec<String>) {
+    let chars: Vec<char> = line.chars().collect();
+    let (tokens, word_start) = tokenize(&chars);
+    let word: String = chars[word_start..].iter().collect();
+
+    // 単語の途中の `$` 以降が変数名なら変数名を補完する
+    if let Some(dollar) = word.rfind('$') {
+        let name = word[dollar + 1..].trim_start_matches('{');
+        if name.chars().all(is_varname_char) {
+            let start = word_start + word[..dollar].chars().count();
+            let braced = word[dollar + 1..].starts_with('{');
+            return (start, variable_candidates(shell, name, braced));
+        }
+    }
+
+    // 今の単語がコマンド名の位置にあるか、どのコマンドの引数か
+    let mut expect_command = true;
+    let mut command: Option<String> = None;
+    let mut args: Vec<String> = Vec::new();
+    let mut redirect = false;
+    for token in tokens {
+        match token {
+            Token::Operator(op) => {
+                if op.starts_with('<') || op.starts_with('>') {
+                    redirect = true;
+                } else if op != "}" && op != ")" {
+                    expect_command = true;
+                    command = None;
+                    args.clear();
+                }
+            }
+            Token::Word(w) => {
+                if redirect {
+                    redirect = false;
+                } else if expect_command {
+                    if !KEYWORDS.contains(&w.as_str()) {
+                        expect_command = false;
+                        command = Some(w);
+                    }
+                } else {
+                    args.push(w);
+                }
+            }
+        }
+    }
+
+    let candidates = if redirect {
+        file_candidates(&word, false)
+    } else if expect_command {
+        if word.contains('/') {
+            file_candidates(&word, true)
+        } else {
+            command_candidates(shell, &word)
+        }
+    } else {
+        let command = command.unwrap_or_default();
+        match shell.completions.get(&command).cloned() {
+            Some(spec) => {
+                let prev = args.last().cloned().unwrap_or_else(|| command.clone());
+                spec_candidates(shell, &spec, &command, &word, &prev)
+            }
+            None => file_candidates(&word, false),
+        }
+    };
+    (word_start, candidates)
+}
+
+// 単語と演算子に区切り、最後の (カーソル位置の) 単語の開始位置を返す
+// 最後の単語は区切ったものには含めない
+fn tokenize(chars: &[char]) -> (Vec<Token>, usize) {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut word_start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            // `${name}` の `{` と `}` は単語の一部
+            '{' if word.ends_with('$') => word.push(c),
+            '}' if word.contains("${") => word.push(c),
+            ' ' | '\t' | '\n' | ';' | '&' | '|' | '(' | ')' | '{' | '}' | '<' | '>' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                if !c.is_whitespace() {
+                    let mut op = c.to_string();
+                    // `&&`, `||`, `|&`, `<<-`, `>=!` などをまとめる
+                    while let Some(&next) = chars.get(i + 1) {
+                        let continues = match c {
+                            '&' => next == '&',
+                            '|' => next == '|' || next == '&',
+                            '<' => next == '<' || next == '-',
+                            '>' => next == '=' || next == '+' || next == '!',
+                            _ => false,
+                        };
+                        if !continues {
+                            break;
+                        }
+                        op.push(next);
+                        i += 1;
+                    }
+                    tokens.push(Token::Operator(op));
+                }
+                word_start = i + 1;
+            }
+            '\\' => {
+                word.push(c);
+                if let Some(&next) = chars.get(i + 1) {
+                    word.push(next);
+                    i += 1;
+                }
+            }
+            '\'' | '"' => {
+                word.push(c);
+                while let Some(&next) = chars.get(i + 1) {
+                    word.push(next);
+                    i += 1;
+                    if next == c {
+                        break;
+                    }
+                }
+            }
+            c => word.push(c),
+        }
+        i += 1;
+    }
+    (tokens, word_start)
+}
+
+fn is_varname_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn command_candidates(shell: &Shell, prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = KEYWORDS.iter().map(|k| k.to_string()).collect();
+    let mut s = Some(shell);
+    while let Some(sh) = s {
+        names.extend(sh.command_table.keys().cloned());
+        s = sh.parent;
+    }
+    // $PATH のコマンドはその都度ディレクトリを読む
+    let path = std::env::var("PATH").unwrap_or_default();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            names.extend(
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .filter(|name| name.starts_with(prefix)),
+            );
+        }
+    }
+    finish(
+        names
+            .into_iter()
+            .filter(|n| n.starts_with(prefix))
+            .collect(),
+    )
+}
+
+fn variable_candidates(shell: &Shell, prefix: &str, braced: bool) -> Vec<String> {
+    let mut names: Vec<String> = std::env::vars().map(|(k, _)| k).collect();
+    let mut s = Some(shell);
+    while let Some(sh) = s {
+        names.extend(sh.variables.keys().cloned());
+        s = sh.parent;
+    }
+    let candidates = names
+        .into_iter()
+        .filter(|n| n.starts_with(prefix))
+        .map(|n| {
+            if braced {
+                format!("${{{}}}", n)
+            } else {
+                format!("${}", n)
+            }
+        })
+        .collect();
+    finish(candidates)
+}
+
+// ファイルのパスを補完する (ディレクトリには `/` を付ける)
+fn file_candidates(word: &str, executable_only: bool) -> Vec<String> {
+    let (dir, base) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let base = pattern::unescape(base);
+    let dir_path = pattern::unescape(dir);
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { &dir_path }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !name.starts_with(base.as_str()) || (name.starts_with('.') && !base.starts_with('.')) {
+            continue;
+        }
+        let path = format!("{}{}", dir_path, name);
+        let is_dir = Path::new(&path).is_dir();
+        if executable_only && !is_dir {
+            let executable = fs::metadata(&path)
+                .map(|m| m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if !executable {
+                continue;
+            }
+        }
+        let mut candidate = format!("{}{}", dir, escape(&name));
+        if is_dir {
+            candidate.push('/');
+        }
+        candidates.push(candidate);
+    }
+    finish(candidates)
+}
+
+// `complete` で登録された方法で引数の候補を作る
+fn spec_candidates(
+    shell: &mut Shell,
+    spec: &CompletionSpec,
+    command: &str,
+    word: &str,
+    prev: &str,
+) -> Vec<String> {
+    let words: Vec<String> = match spec {
+        CompletionSpec::Words(words) => words.clone(),
+        CompletionSpec::Function(name) | CompletionSpec::Command(name) => {
+            // 関数やコマンドに (コマンド名, 補完する単語, 直前の単語) を渡し、
+            // 出力の各行を候補にする
+            let exe = Executable::File {
+                assignments: Vec::new(),
+                command_name: Str::Raw(name.clone()),
+                arguments: vec![
+                    Str::Raw(command.to_string()),
+                    Str::Raw(word.to_string()),
+                    Str::Raw(prev.to_string()),
+                ],
+            };
+            let cmd = Command {
+                exe,
+                redirects: Vec::new(),
+            };
+            let pipeline = PipeLine(VecDeque::from(vec![Pipe::PipeLineTerm(cmd)]));
+            let list = List(vec![Connector::ListTerm(pipeline)], None);
+            match Str::SubShellResult(list).extract(shell) {
+                Ok(output) => output.lines().map(str::to_string).collect(),
+                Err(_) => Vec::new(),
+            }
+        }
+    };
+    finish(words.into_iter().filter(|w| w.starts_with(word)).collect())
+}
+
+fn finish(mut candidates: Vec<String>) -> Vec<String> {
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if " \t;&()|<>?*$'\"\\{}#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -1,17 +1,31 @@
+use super::arithmetic;
+use super::input;
+use super::msh_grammar;
+use super::parameter;
+use super::pattern;
+use super::prompt;
+use super::regex;
 use super::structures::*;
+use super::word;
 
 use nix::fcntl;
 use nix::sys;
 use nix::sys::signal;
+use nix::sys::termios;
+use nix::sys::wait::{WaitPidFlag, WaitStatus};
 use nix::unistd;
 
 use std::ffi;
+use std::io::Write;
 
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io;
-use std::path::PathBuf;
-
-use std::cell::RefCell;
+use std::path::{Path, PathBuf};
 
 pub type ExitCode = i32;
 
@@ -24,6 +38,14 @@ fn error_then_exit<E: std::fmt::Display>(e: E) -> ! {
     std::process::exit(1);
 }
 
+// 実行できる普通のファイルか
+fn is_executable(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
 // ファイルを開いてファイルディスクリプタを返す
 fn open_file(path: &str, flag: fcntl::OFlag) -> Result<io::RawFd, String> {
     let permission = sys::stat::Mode::S_IRUSR
@@ -39,86 +61,749 @@ fn open_file(path: &str, flag: fcntl::OFlag) -> Result<io::RawFd, String> {
     })
 }
 
+// シグナルで終了したことを表すメッセージ (例: "Segmentation fault (core dumped)")
+fn signal_description(sig: signal::Signal, core_dumped: bool) -> String {
+    extern "C" {
+        fn strsignal(sig: libc::c_int) -> *const libc::c_char;
+    }
+    let desc = unsafe { ffi::CStr::from_ptr(strsignal(sig as libc::c_int)) };
+    let desc = desc.to_string_lossy();
+    if core_dumped {
+        format!("{} (core dumped)", desc)
+    } else {
+        desc.to_string()
+    }
+}
+
+// パイプラインを構成するプロセスをまとめるプロセスグループ
+pub struct ProcessGroup {
+    pub pgid: Option<unistd::Pid>,
+    pub foreground: bool,
+    pub pipelined: bool, // 複数のコマンドからなるパイプラインか
+    pub processes: Vec<Process>,
+}
+
+impl ProcessGroup {
+    pub fn new(foreground: bool) -> ProcessGroup {
+        ProcessGroup {
+            pgid: None,
+            foreground,
+            pipelined: false,
+            processes: Vec::new(),
+        }
+    }
+    // fork直後の親プロセス側で呼ぶ
+    fn join(&mut self, shell: &Shell, child: unistd::Pid) {
+        let pgid = *self.pgid.get_or_insert(child);
+        if shell.job_control.is_some() {
+            // 子プロセス側と競合しても良いように両方で設定する
+            let _ = unistd::setpgid(child, pgid);
+        }
+        self.processes.push(Process {
+            pid: Some(child),
+            state: ProcessState::Running,
+        });
+    }
+    // シェル自身で実行したコマンドの終了コードを記録する
+    fn done(&mut self, exit_code: ExitCode) {
+        self.processes.push(Process {
+            pid: None,
+            state: ProcessState::Exited(exit_code),
+        });
+    }
+    // fork直後の子プロセス側で呼ぶ
+    fn enter(&self, shell: &Shell) {
+        if shell.job_control.is_some() {
+            let pid = unistd::getpid();
+            let pgid = self.pgid.unwrap_or(pid);
+            let _ = unistd::setpgid(pid, pgid);
+            if self.foreground {
+                let _ = unistd::tcsetpgrp(0, pgid);
+            }
+        }
+
+        // シェルが無視しているシグナルを元に戻す
+        // (SIGPIPE は Rust の実行時ライブラリが無視するようにしている)
+        let default = signal::SigAction::new(
+            signal::SigHandler::SigDfl,
+            signal::SaFlags::empty(),
+            signal::SigSet::empty(),
+        );
+        for sig in [
+            signal::Signal::SIGINT,
+            signal::Signal::SIGPIPE,
+            signal::Signal::SIGTSTP,
+            signal::Signal::SIGTTIN,
+            signal::Signal::SIGTTOU,
+        ]
+        .iter()
+        {
+            unsafe {
+                let _ = signal::sigaction(*sig, &default);
+            }
+        }
+    }
+}
+
+impl Process {
+    // 状態の変化を1つ受け取る
+    fn wait(&mut self, flags: WaitPidFlag) -> Result<(), String> {
+        let pid = match (self.pid, self.state) {
+            (Some(pid), ProcessState::Running) | (Some(pid), ProcessState::Stopped) => pid,
+            _ => return Ok(()),
+        };
+        self.state = match sys::wait::waitpid(pid, Some(flags)) {
+            Ok(WaitStatus::Exited(_, code)) => ProcessState::Exited(code),
+            Ok(WaitStatus::Signaled(_, sig, core)) => ProcessState::Signaled(sig, core),
+            Ok(WaitStatus::Stopped(..)) => ProcessState::Stopped,
+            Ok(WaitStatus::Continued(_)) => ProcessState::Running,
+            Ok(_) => self.state,
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => self.state,
+            // 既に回収されている
+            Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => ProcessState::Exited(0),
+            Err(e) => return Err(format!("waitpid: {}", e)),
+        };
+        Ok(())
+    }
+    fn exit_code(&self) -> ExitCode {
+        match self.state {
+            ProcessState::Exited(code) => code,
+            ProcessState::Signaled(sig, _) => 128 + sig as i32,
+            ProcessState::Stopped => 128 + signal::Signal::SIGTSTP as i32,
+            ProcessState::Running => 0,
+        }
+    }
+}
+
+impl Job {
+    pub fn state(&self) -> JobState {
+        let states = self.processes.iter().map(|p| p.state);
+        if states.clone().any(|s| s == ProcessState::Stopped) {
+            JobState::Stopped
+        } else if states.clone().any(|s| s == ProcessState::Running) {
+            JobState::Running
+        } else {
+            JobState::Done(self.exit_code())
+        }
+    }
+    // パイプラインの終了コードは最後のコマンドのもの
+    pub fn exit_code(&self) -> ExitCode {
+        if self
+            .processes
+            .iter()
+            .any(|p| p.state == ProcessState::Stopped)
+        {
+            return 128 + signal::Signal::SIGTSTP as i32;
+        }
+        self.processes.last().map(Process::exit_code).unwrap_or(0)
+    }
+    // 停止しているプロセスを実行中として扱う (SIGCONTを送った後に呼ぶ)
+    pub fn mark_continued(&mut self) {
+        for p in self.processes.iter_mut() {
+            if p.state == ProcessState::Stopped {
+                p.state = ProcessState::Running;
+            }
+        }
+    }
+}
+
 impl<'a> Shell<'a> {
     pub fn new() -> Shell<'a> {
         let mut shell = Shell {
             parent: None,
             command_table: HashMap::new(),
+            command_hash: RefCell::new(CommandHash::default()),
             variables: HashMap::new(),
+            arrays: HashMap::new(),
+            locals: HashSet::new(),
+            unset_globals: HashSet::new(),
+            jobs: Vec::new(),
+            job_control: None,
+            interactive: false,
+            pid: unistd::getpid(),
+            name: "msh".to_string(),
+            positional: Vec::new(),
+            last_status: 0,
+            last_bg_pid: None,
+            loop_depth: 0,
+            flow: None,
+            in_function: false,
+            options: Options::new(),
+            completions: HashMap::new(),
         };
         super::builtin_commands::reload_path(&mut shell, Vec::new());
         shell
     }
     pub fn fromParent(parent: &'a Shell<'a>) -> Shell<'a> {
-        let mut shell = Shell {
+        Shell {
             parent: Some(&parent),
             command_table: parent.command_table.clone(),
+            command_hash: RefCell::new(CommandHash::default()),
             variables: HashMap::new(),
-        };
-        super::builtin_commands::reload_path(&mut shell, Vec::new());
+            arrays: HashMap::new(),
+            locals: HashSet::new(),
+            unset_globals: HashSet::new(),
+            jobs: Vec::new(),
+            job_control: None,
+            interactive: false,
+            pid: parent.pid,
+            name: parent.name.clone(),
+            positional: parent.positional.clone(),
+            last_status: parent.last_status,
+            last_bg_pid: parent.last_bg_pid,
+            loop_depth: 0,
+            flow: None,
+            in_function: parent.in_function,
+            options: parent.options,
+            completions: parent.completions.clone(),
+        }
+    }
+
+    // シェル変数を探す (関数の中では呼び出し元を順にたどる)
+    pub fn get_variable(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+        if self.locals.contains(name) || self.unset_globals.contains(name) {
+            return None;
+        }
+        self.parent.and_then(|p| p.get_variable(name))
+    }
+    pub fn set_variable(&mut self, name: String, value: String) {
+        self.unset_globals.remove(&name);
+        self.arrays.remove(&name);
+        self.variables.insert(name, value);
+    }
+    // 配列を探す (関数の中では呼び出し元を順にたどる)
+    pub fn get_array(&self, name: &str) -> Option<Vec<String>> {
+        if let Some(values) = self.arrays.get(name) {
+            return Some(values.clone());
+        }
+        if self.variables.contains_key(name)
+            || self.locals.contains(name)
+            || self.unset_globals.contains(name)
+        {
+            return None;
+        }
+        self.parent.and_then(|p| p.get_array(name))
+    }
+    // 配列を設定する (`$name` は最初の要素になる)
+    pub fn set_array(&mut self, name: String, values: Vec<String>) {
+        self.set_variable(name.clone(), values.first().cloned().unwrap_or_default());
+        self.arrays.insert(name, values);
+    }
+    // `name=value` による代入
+    // 変数は環境変数から先に探すので、環境変数にあればそちらを書き換える
+    pub fn assign_variable(&mut self, name: String, value: String) {
+        if std::env::var_os(&name).is_some() {
+            std::env::set_var(name, value);
+        } else {
+            self.set_variable(name, value);
+        }
+    }
+    pub fn unset_variable(&mut self, name: &str) {
+        self.variables.remove(name);
+        self.arrays.remove(name);
+        if self.in_function && !self.locals.contains(name) {
+            // 関数から戻るときに呼び出し元でも削除する
+            self.unset_globals.insert(name.to_string());
+        }
+    }
+
+    // コマンドを探す
+    // コマンドテーブル (関数の中では呼び出し元を順にたどる) になければ $PATH から探す
+    pub fn find_command(&self, name: &str) -> Option<CommandType> {
+        let mut shell = Some(self);
+        while let Some(sh) = shell {
+            if let Some(cmd) = sh.command_table.get(name) {
+                return Some(cmd.clone());
+            }
+            shell = sh.parent;
+        }
+        self.search_path(name).map(CommandType::External)
+    }
+
+    // 記憶は一番外側の Shell にまとめる
+    fn root(&self) -> &Shell<'a> {
+        let mut shell = self;
+        while let Some(parent) = shell.parent {
+            shell = parent;
+        }
         shell
     }
-    pub fn wait(&self) -> ExitCode {
+
+    // $PATH からコマンドを探し、見つけたパスを覚えておく
+    pub fn search_path(&self, name: &str) -> Option<PathBuf> {
+        if name.is_empty() || name.contains('/') {
+            return None;
+        }
+        let path = std::env::var("PATH").unwrap_or_default();
+        let mut hash = self.root().command_hash.borrow_mut();
+        if hash.path != path {
+            hash.path = path.clone();
+            hash.entries.clear();
+        }
+        // 覚えているパスのファイルが消えていれば探し直す
+        if let Some(found) = hash.entries.get(name) {
+            if is_executable(found) {
+                return Some(found.clone());
+            }
+        }
+        let found = path
+            .split(':')
+            .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+            .find(|p| is_executable(p))?;
+        hash.entries.insert(name.to_string(), found.clone());
+        Some(found)
+    }
+
+    // 覚えているコマンドのパスの一覧
+    pub fn hashed_commands(&self) -> Vec<(String, PathBuf)> {
+        let hash = self.root().command_hash.borrow();
+        hash.entries
+            .iter()
+            .map(|(name, path)| (name.clone(), path.clone()))
+            .collect()
+    }
+
+    // 覚えているコマンドのパスを全て忘れる
+    pub fn forget_commands(&self) {
+        self.root().command_hash.borrow_mut().entries.clear();
+    }
+
+    // 関数を呼び出す
+    // 関数の中は呼び出し元を親とする新しい Shell で実行し、
+    // local で宣言されたもの以外の変数や定義された関数は戻るときに呼び出し元へ書き戻す
+    pub fn call_function(&mut self, body: List, args: Vec<String>) -> Result<ExitCode, String> {
+        let jobs = std::mem::take(&mut self.jobs);
+        let job_control = self.job_control.take();
+        let mut frame = Shell {
+            parent: Some(self),
+            command_table: HashMap::new(),
+            command_hash: RefCell::new(CommandHash::default()),
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            locals: HashSet::new(),
+            unset_globals: HashSet::new(),
+            jobs,
+            job_control,
+            interactive: self.interactive,
+            pid: self.pid,
+            name: self.name.clone(),
+            positional: args,
+            last_status: self.last_status,
+            last_bg_pid: self.last_bg_pid,
+            loop_depth: 0,
+            flow: None,
+            in_function: true,
+            options: self.options,
+            completions: HashMap::new(),
+        };
+        let result = frame.exec(body);
+
+        let Shell {
+            command_table,
+            variables,
+            arrays,
+            locals,
+            unset_globals,
+            jobs,
+            job_control,
+            last_bg_pid,
+            flow,
+            options,
+            completions,
+            ..
+        } = frame;
+        if flow == Some(Flow::Interrupt) {
+            self.flow = flow;
+        }
+        self.options = options;
+        self.completions.extend(completions);
+        self.jobs = jobs;
+        self.job_control = job_control;
+        self.last_bg_pid = last_bg_pid;
+        self.command_table.extend(command_table);
+        for name in unset_globals {
+            self.unset_variable(&name);
+        }
+        for (name, value) in variables {
+            if !locals.contains(&name) {
+                self.set_variable(name, value);
+            }
+        }
+        for (name, values) in arrays {
+            if !locals.contains(&name) {
+                self.set_array(name, values);
+            }
+        }
+        result
+    }
+
+    // fork した子プロセスで、サブシェルとして実行を続けるために呼ぶ
+    fn reset_for_subshell(&mut self) {
+        self.job_control = None;
+        self.interactive = false;
+        self.jobs.clear();
+    }
+
+    // 特殊変数 ($?, $$, $!, $#, $@, $*) と位置パラメータ ($0, $1, ...) の値
+    // それ以外の名前なら None を返す
+    pub fn special_variable(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "?" => self.last_status.to_string(),
+            "$" => self.pid.to_string(),
+            "!" => self
+                .last_bg_pid
+                .map(|pid| pid.to_string())
+                .unwrap_or_default(),
+            "#" => self.positional.len().to_string(),
+            "@" => self.positional.join(" "),
+            // "$*" は $IFS の最初の文字でつなげる
+            "*" => {
+                let sep: String = word::ifs(self).chars().take(1).collect();
+                self.positional.join(&sep)
+            }
+            "0" => self.name.clone(),
+            _ => {
+                let n = name.parse::<usize>().ok()?;
+                n.checked_sub(1)
+                    .and_then(|i| self.positional.get(i))
+                    .cloned()
+                    .unwrap_or_default()
+            }
+        };
+        Some(value)
+    }
+
+    // 標準入力が端末ならジョブ制御を有効にする
+    pub fn enable_job_control(&mut self) -> Result<(), String> {
+        if !unistd::isatty(0).unwrap_or(false) {
+            return Ok(());
+        }
+
+        // フォアグラウンドになるまで待つ
+        loop {
+            let pgrp = unistd::getpgrp();
+            if unistd::tcgetpgrp(0).map_err(error_to_string)? == pgrp {
+                break;
+            }
+            signal::killpg(pgrp, signal::Signal::SIGTTIN).map_err(error_to_string)?;
+        }
+
+        let ignore = signal::SigAction::new(
+            signal::SigHandler::SigIgn,
+            signal::SaFlags::empty(),
+            signal::SigSet::empty(),
+        );
+        for sig in [
+            signal::Signal::SIGTSTP,
+            signal::Signal::SIGTTIN,
+            signal::Signal::SIGTTOU,
+        ]
+        .iter()
+        {
+            unsafe {
+                signal::sigaction(*sig, &ignore).map_err(error_to_string)?;
+            }
+        }
+
+        // 自分自身のプロセスグループを作って端末を握る
+        // (セッションリーダーの場合は失敗するが、その場合は既にグループのリーダーである)
+        let pid = unistd::getpid();
+        let _ = unistd::setpgid(pid, pid);
+        let pgid = unistd::getpgrp();
+        unistd::tcsetpgrp(0, pgid).map_err(error_to_string)?;
+
+        let tmodes = termios::tcgetattr(0).map_err(error_to_string)?;
+        self.job_control = Some(JobControl { pgid, tmodes });
+        Ok(())
+    }
+
+    pub fn next_job_id(&self) -> usize {
+        self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1
+    }
+
+    // ジョブの終了か停止を待つ
+    // ジョブ制御が有効なら待っている間は端末をジョブに渡す
+    // 停止した場合はジョブテーブルに登録する
+    pub fn wait_foreground(&mut self, mut job: Job) -> Result<ExitCode, String> {
+        if self.job_control.is_some() {
+            let _ = unistd::tcsetpgrp(0, job.pgid);
+            if let Some(tmodes) = job.tmodes.take() {
+                let _ = termios::tcsetattr(0, termios::SetArg::TCSADRAIN, &tmodes);
+            }
+        }
+
+        let mut result = Ok(());
+        for p in job.processes.iter_mut() {
+            while result.is_ok() && p.state == ProcessState::Running {
+                result = p.wait(WaitPidFlag::WUNTRACED);
+            }
+            if result.is_err() || p.state == ProcessState::Stopped {
+                break;
+            }
+        }
+        let stopped = job.state() == JobState::Stopped;
+        for p in job.processes.iter() {
+            if let ProcessState::Signaled(sig, core) = p.state {
+                match sig {
+                    // 割り込みとパイプの切断は報告しない
+                    // 割り込まれたら実行中のループや複合コマンドも中断する
+                    signal::Signal::SIGINT => {
+                        println!();
+                        if self.interactive {
+                            self.flow = Some(Flow::Interrupt);
+                        }
+                    }
+                    signal::Signal::SIGPIPE => {}
+                    _ => eprintln!("{}", signal_description(sig, core)),
+                }
+            }
+        }
+
+        // 端末をシェルに戻す
+        if let Some(jc) = &self.job_control {
+            let _ = unistd::tcsetpgrp(0, jc.pgid);
+            if stopped {
+                job.tmodes = termios::tcgetattr(0).ok();
+            }
+            let _ = termios::tcsetattr(0, termios::SetArg::TCSADRAIN, &jc.tmodes);
+        }
+        result?;
+
+        let exit_code = job.exit_code();
+        if stopped {
+            println!();
+            println!("{}", self.format_job(&job, '+'));
+            self.jobs.push(job);
+        }
+        Ok(exit_code)
+    }
+
+    // ブロックせずにジョブの状態を更新する
+    pub fn update_jobs(&mut self) -> Result<(), String> {
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        for job in self.jobs.iter_mut() {
+            for p in job.processes.iter_mut() {
+                p.wait(flags)?;
+            }
+        }
+        Ok(())
+    }
+
+    // ジョブテーブルでの位置に応じた印 (`+`がカレントジョブ、`-`がその前のジョブ)
+    pub fn job_mark(&self, index: usize) -> char {
+        let len = self.jobs.len();
+        if index + 1 == len {
+            '+'
+        } else if index + 2 == len {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    pub fn format_job(&self, job: &Job, mark: char) -> String {
+        let last = job.processes.last().map(|p| p.state);
+        let state = match (job.state(), last) {
+            (JobState::Running, _) => "Running".to_string(),
+            (JobState::Stopped, _) => "Stopped".to_string(),
+            (JobState::Done(_), Some(ProcessState::Signaled(sig, core))) => {
+                signal_description(sig, core)
+            }
+            (JobState::Done(0), _) => "Done".to_string(),
+            (JobState::Done(code), _) => format!("Exit {}", code),
+        };
+        format!("[{}]{}  {:<10} {}", job.id, mark, state, job.command)
+    }
+
+    // 終了したバックグラウンドのジョブを通知してジョブテーブルから取り除く
+    pub fn notify_jobs(&mut self) {
+        if let Err(e) = self.update_jobs() {
+            eprintln!("error: {}", e);
+        }
+        for (i, job) in self.jobs.iter().enumerate() {
+            if let JobState::Done(_) = job.state() {
+                println!("{}", self.format_job(job, self.job_mark(i)));
+            }
+        }
+        self.remove_done_jobs();
+    }
+
+    pub fn remove_done_jobs(&mut self) {
+        self.jobs
+            .retain(|j| !matches!(j.state(), JobState::Done(_)));
+    }
+
+    // プロンプトを表示しない入力では展開しない ($(...) を毎行実行しないように)
+    fn prompt(&mut self, input: &input::Input, name: &str, default: &str) -> String {
+        if input.is_interactive() {
+            prompt::expand_variable(self, name, default)
+        } else {
+            String::new()
+        }
+    }
+
+    // 入力からコマンドを読み込んで終わりまで実行し、最後の終了コードを返す
+    pub fn run(&mut self, input: &mut input::Input) -> ExitCode {
         let mut exit_status = 0;
         loop {
-            if let Ok(status) = sys::wait::waitpid(unistd::Pid::from_raw(-1), None) {
-                match status {
-                    sys::wait::WaitStatus::Exited(_, code) => {
-                        exit_status = code;
+            if input.is_interactive() {
+                self.notify_jobs();
+            }
+            let primary = self.prompt(input, "PS1", prompt::DEFAULT_PS1);
+            let mut line = match input.read_command_line(&primary, self) {
+                Some(line) => line,
+                None => break,
+            };
+
+            // 入力の途中で終わっている場合は続きの行を読む
+            let parsed = loop {
+                match msh_grammar::command_line(line.trim(), self.options.posix) {
+                    Err(ref e) if e.offset >= line.trim().len() => {
+                        let continuation = self.prompt(input, "PS2", prompt::DEFAULT_PS2);
+                        match input.read_command_line(&continuation, self) {
+                            Some(next) => line.push_str(&next),
+                            None => {
+                                break msh_grammar::command_line(line.trim(), self.options.posix)
+                            }
+                        }
                     }
-                    _ => {}
-                    // e => eprintln!("waitpid: change state: {:?}", e),
+                    r => break r,
                 }
+                if input.take_interrupted() {
+                    break Ok(None);
+                }
+            };
+            if input.take_interrupted() {
+                // 途中まで入力したコマンドは実行しない
+                input.discard_heredocs();
                 continue;
             }
-            break;
+
+            let mut list = match parsed {
+                Ok(Some(list)) => list,
+                Ok(None) => continue,
+                Err(e) => {
+                    input.discard_heredocs();
+                    if input.is_interactive() {
+                        println!("parsing error. {}", e);
+                        continue;
+                    }
+                    eprintln!("parsing error. {}", e);
+                    return 2;
+                }
+            };
+            input.read_heredocs(&mut list);
+
+            match self.exec(list) {
+                Ok(code) => {
+                    exit_status = code;
+                    if input.is_interactive() {
+                        println!("=> {}", code);
+                    }
+                }
+                Err(e) => {
+                    exit_status = 1;
+                    self.last_status = exit_status;
+                    if input.is_interactive() {
+                        println!("error: {}", e);
+                    } else {
+                        eprintln!("error: {}", e);
+                    }
+                }
+            }
+
+            // 割り込まれたら次の入力に進む (source で読んでいるファイルは残りを実行しない)
+            if self.flow == Some(Flow::Interrupt) {
+                if !input.is_interactive() {
+                    break;
+                }
+                self.flow = None;
+            }
         }
         exit_status
     }
+
+    // ファイルの内容を今のシェルで実行する (source や起動時の ~/.mshrc)
+    pub fn run_file(&mut self, path: &std::path::Path) -> Result<ExitCode, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("`{}`: {}", path.display(), e))?;
+        let mut input = input::Input::from_reader(std::io::BufReader::new(file));
+        Ok(self.run(&mut input))
+    }
+
     pub fn exec(&mut self, cmds: List) -> Result<ExitCode, String> {
         let mut exit_status = 0;
         if cmds.1.is_some() {
-            println!("[run on the background]");
+            let cmds = List(cmds.0, None);
+            let text = cmds.to_string();
             let cmds = Command {
-                exe: Executable::SubShell(List(cmds.0, None)),
-                redirect_in: None,
-                redirect_out: None,
-                redirect_err: None,
+                exe: Executable::SubShell(cmds),
+                redirects: Vec::new(),
             };
-            let mut subsehll = Shell::fromParent(self);
-            cmds.exec(&mut subsehll, 0, 1, 2, Vec::new());
+            let mut group = ProcessGroup::new(false);
+            cmds.exec(self, 0, 1, 2, Vec::new(), &mut group)?;
+
+            if let Some(pgid) = group.pgid {
+                let job = Job {
+                    id: self.next_job_id(),
+                    pgid,
+                    command: text,
+                    processes: group.processes,
+                    tmodes: None,
+                };
+                self.last_bg_pid = Some(pgid);
+                if self.interactive {
+                    println!("[{}] {}", job.id, pgid);
+                }
+                self.jobs.push(job);
+            }
             return Ok(0);
         }
 
+        // `&&` と `||` は直前に実行したパイプラインの終了コードで次を実行するか決める
+        let mut run_next = true;
         for connector in cmds.0 {
-            match connector {
-                Connector::Continue(pipeline) => {
-                    pipeline.exec(self, 0, 1, 2)?;
-                    exit_status = self.wait();
-                    // 終了コードに関わらず続行
-                }
-                Connector::And(pipeline) => {
-                    pipeline.exec(self, 0, 1, 2)?;
-                    exit_status = self.wait();
-                    if exit_status != 0 {
-                        break;
-                    }
-                }
-                Connector::Or(pipeline) => {
-                    pipeline.exec(self, 0, 1, 2)?;
-                    exit_status = self.wait();
-                    if exit_status == 0 {
-                        break;
-                    }
-                }
-                Connector::ListTerm(pipeline) => {
-                    pipeline.exec(self, 0, 1, 2)?;
-                    exit_status = self.wait();
-                }
+            if self.flow.is_some() {
+                break; // break/continue が実行された
+            }
+            let (pipeline, run_if) = match connector {
+                Connector::Continue(pipeline) => (pipeline, None),
+                Connector::And(pipeline) => (pipeline, Some(true)),
+                Connector::Or(pipeline) => (pipeline, Some(false)),
+                Connector::ListTerm(pipeline) => (pipeline, None),
+            };
+            if run_next {
+                exit_status = self.exec_pipeline(pipeline)?;
+                self.last_status = exit_status;
             }
+            run_next = match run_if {
+                Some(success) => (exit_status == 0) == success,
+                None => true, // 終了コードに関わらず続行
+            };
         }
         Ok(exit_status)
     }
+
+    // パイプラインをフォアグラウンドで実行して終了コードを返す
+    fn exec_pipeline(&mut self, pipeline: PipeLine) -> Result<ExitCode, String> {
+        let text = pipeline.to_string();
+        let mut group = ProcessGroup::new(true);
+        pipeline.exec(self, 0, 1, 2, &mut group)?;
+        let job = Job {
+            id: self.next_job_id(),
+            pgid: match group.pgid {
+                Some(pgid) => pgid,
+                // 組み込みコマンドだけだった
+                None => return Ok(group.processes.last().map(Process::exit_code).unwrap_or(0)),
+            },
+            command: text,
+            processes: group.processes,
+            tmodes: None,
+        };
+        self.wait_foreground(job)
+    }
 }
 
 impl PipeLine {
@@ -128,28 +813,35 @@ impl PipeLine {
         stdin: io::RawFd,
         stdout: io::RawFd,
         stderr: io::RawFd,
+        group: &mut ProcessGroup,
     ) -> Result<(), String> {
         let pipeline = self.0;
         if pipeline.is_empty() {
             panic!("PipeLine must not be empty.");
         }
 
+        group.pipelined = pipeline.len() > 1;
         let mut next_in = stdin;
         for pipe in pipeline {
             let p = unistd::pipe().map_err(error_to_string)?;
 
             match pipe {
                 Pipe::Stdout(cmd) => {
-                    cmd.exec(shell, next_in, p.1, stderr, vec![p.0])?;
+                    cmd.exec(shell, next_in, p.1, stderr, vec![p.0], group)?;
                 }
                 Pipe::Both(cmd) => {
-                    cmd.exec(shell, next_in, p.1, p.1, vec![p.0])?;
+                    cmd.exec(shell, next_in, p.1, p.1, vec![p.0], group)?;
                 }
                 Pipe::PipeLineTerm(cmd) => {
-                    cmd.exec(shell, next_in, stdout, stderr, Vec::new())?;
+                    cmd.exec(shell, next_in, stdout, stderr, Vec::new(), group)?;
                 }
             }
 
+            // 前の段の読み込み側は次の段に渡したので、シェルの側では閉じる
+            // (開いたままだと、読む側が終了しても書く側がいつまでも待ち続ける)
+            if next_in != stdin {
+                unistd::close(next_in).map_err(error_to_string)?;
+            }
             next_in = p.0;
             unistd::close(p.1).map_err(error_to_string)?;
         }
@@ -158,36 +850,127 @@ impl PipeLine {
     }
 }
 
-impl RedirectIn {
-    pub fn extract(self, shell: &mut Shell) -> Result<io::RawFd, String> {
-        match self {
-            RedirectIn::Normal(filepath) => {
-                open_file(filepath.extract(shell)?.as_str(), fcntl::OFlag::O_RDONLY)
+// コマンドに渡すファイルディスクリプタの割り当て
+// (番号 → 実際に開いている fd、閉じる場合は None)
+// リダイレクトは書かれた順にこれを書き換える
+pub struct FdMap(BTreeMap<io::RawFd, Option<io::RawFd>>);
+
+impl FdMap {
+    pub fn new(stdin: io::RawFd, stdout: io::RawFd, stderr: io::RawFd) -> FdMap {
+        let mut map = BTreeMap::new();
+        map.insert(0, Some(stdin));
+        map.insert(1, Some(stdout));
+        map.insert(2, Some(stderr));
+        FdMap(map)
+    }
+
+    // 割り当てのない番号はシェル自身が開いているものをそのまま使う
+    pub fn get(&self, fd: io::RawFd) -> Option<io::RawFd> {
+        match self.0.get(&fd) {
+            Some(source) => *source,
+            None if fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFD).is_ok() => Some(fd),
+            None => None,
+        }
+    }
+
+    // 割り当てのとおりに dup2 する
+    // 複製元と複製先の番号が重なっても壊れないように、いったん大きい番号に複製してから移す
+    fn install(&self) -> Result<(), String> {
+        let base = self.base();
+        let mut moves = Vec::new();
+        for (&fd, &source) in self.0.iter() {
+            let tmp = match source {
+                Some(source) => Some(
+                    fcntl::fcntl(source, fcntl::FcntlArg::F_DUPFD_CLOEXEC(base))
+                        .map_err(error_to_string)?,
+                ),
+                None => None,
+            };
+            moves.push((fd, tmp));
+        }
+        for (fd, tmp) in moves {
+            match tmp {
+                Some(tmp) => {
+                    unistd::dup2(tmp, fd).map_err(error_to_string)?;
+                    unistd::close(tmp).map_err(error_to_string)?;
+                }
+                None => {
+                    let _ = unistd::close(fd);
+                }
             }
-            // TODO ヒアドキュメント実装
-            RedirectIn::Heredoc(end_of_string) => unimplemented!(),
         }
+        Ok(())
+    }
+
+    // 一時的に複製する fd に使う番号の下限
+    fn base(&self) -> io::RawFd {
+        self.0.keys().max().map_or(0, |fd| fd + 1).max(10)
     }
 }
-impl RedirectOut {
-    pub fn extract(self, shell: &mut Shell) -> Result<io::RawFd, String> {
-        match self {
-            RedirectOut::Normal(filepath) => open_file(
-                filepath.extract(shell)?.as_str(),
-                fcntl::OFlag::O_CREAT | fcntl::OFlag::O_WRONLY | fcntl::OFlag::O_EXCL,
-            ),
-            RedirectOut::Overwrite(filepath) => open_file(
-                &filepath.extract(shell)?.as_str(),
-                fcntl::OFlag::O_CREAT | fcntl::OFlag::O_WRONLY | fcntl::OFlag::O_TRUNC,
-            ),
-            RedirectOut::Append(filepath) => open_file(
-                &filepath.extract(shell)?.as_str(),
-                fcntl::OFlag::O_CREAT | fcntl::OFlag::O_WRONLY | fcntl::OFlag::O_APPEND,
-            ),
-        }
+
+impl Redirect {
+    // リダイレクト先を開いて fds に割り当てる (開いたものは opened に加える)
+    fn apply(
+        self,
+        shell: &mut Shell,
+        fds: &mut FdMap,
+        opened: &mut Vec<io::RawFd>,
+    ) -> Result<(), String> {
+        use fcntl::OFlag;
+        let fd = match self.target {
+            RedirectTarget::Input(path) => open_file(&path.extract(shell)?, OFlag::O_RDONLY)?,
+            RedirectTarget::Heredoc(doc) => {
+                let body = doc.body.extract(shell)?;
+                write_to_tempfile(body.as_bytes())?
+            }
+            RedirectTarget::Output(path) => open_file(
+                &path.extract(shell)?,
+                OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_EXCL,
+            )?,
+            RedirectTarget::Overwrite(path) => open_file(
+                &path.extract(shell)?,
+                OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_TRUNC,
+            )?,
+            RedirectTarget::Append(path) => open_file(
+                &path.extract(shell)?,
+                OFlag::O_CREAT | OFlag::O_WRONLY | OFlag::O_APPEND,
+            )?,
+            RedirectTarget::ReadWrite(path) => {
+                open_file(&path.extract(shell)?, OFlag::O_CREAT | OFlag::O_RDWR)?
+            }
+            RedirectTarget::Duplicate(source) => {
+                let source = source.extract(shell)?;
+                let bad_fd = || error_to_string(format!("`{}`: bad file descriptor.", source));
+                let n = source.parse().map_err(|_| bad_fd())?;
+                let source = fds.get(n).ok_or_else(bad_fd)?;
+                fds.0.insert(self.fd, Some(source));
+                return Ok(());
+            }
+            RedirectTarget::Close => {
+                fds.0.insert(self.fd, None);
+                return Ok(());
+            }
+        };
+        opened.push(fd);
+        fds.0.insert(self.fd, Some(fd));
+        Ok(())
     }
 }
 
+// 名前のない一時ファイルに書き込み、先頭に戻したファイルディスクリプタを返す
+fn write_to_tempfile(content: &[u8]) -> Result<io::RawFd, String> {
+    let tmpdir = std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string());
+    let template = format!("{}/msh-heredoc-XXXXXX", tmpdir.trim_end_matches('/'));
+    let (fd, path) = unistd::mkstemp(template.as_str()).map_err(error_to_string)?;
+    unistd::unlink(&path).map_err(error_to_string)?;
+
+    let mut written = 0;
+    while written < content.len() {
+        written += unistd::write(fd, &content[written..]).map_err(error_to_string)?;
+    }
+    unistd::lseek(fd, 0, unistd::Whence::SeekSet).map_err(error_to_string)?;
+    Ok(fd)
+}
 impl Command {
     pub fn exec(
         self,
@@ -196,48 +979,62 @@ impl Command {
         stdout: io::RawFd,
         stderr: io::RawFd,
         to_close: Vec<io::RawFd>,
+        group: &mut ProcessGroup,
     ) -> Result<(), String> {
-        let stdin = if let Some(r) = self.redirect_in {
-            r.extract(shell)?
-        } else {
-            stdin
-        };
-        let stdout = if let Some(r) = self.redirect_out {
-            r.extract(shell)?
-        } else {
-            stdout
-        };
-        let stderr = if let Some(r) = self.redirect_err {
-            r.extract(shell)?
-        } else {
-            stderr
-        };
+        // リダイレクトのために開いたファイルは実行後に閉じる
+        let mut fds = FdMap::new(stdin, stdout, stderr);
+        let mut opened = Vec::new();
+        for redirect in self.redirects {
+            if let Err(e) = redirect.apply(shell, &mut fds, &mut opened) {
+                for fd in opened {
+                    let _ = unistd::close(fd);
+                }
+                return Err(e);
+            }
+        }
 
         match self.exe {
             Executable::File {
+                assignments,
                 command_name,
                 arguments,
             } => {
                 // コマンドテーブルから引く
-                fn command_search(shell: &mut Shell, name: &str) -> Result<CommandType, String> {
-                    match shell.command_table.get(name) {
-                        Some(cmd) => Ok(cmd.clone()),
+                fn command_search(shell: &mut Shell, name: &str) -> Option<CommandType> {
+                    match shell.find_command(name) {
+                        Some(cmd) => Some(cmd),
                         None => {
                             // パスとして存在するか
                             if !std::path::Path::new(name).exists() {
-                                return Err(error_to_string(format!("`{}` not found.", name)));
+                                return None;
                             }
-                            Ok(CommandType::External(PathBuf::from(name)))
+                            Some(CommandType::External(PathBuf::from(name)))
                         }
                     }
                 }
 
-                let command_name = command_name.extract(shell)?;
-                let cmd = command_search(shell, command_name.as_str())?;
-
-                let mut arguments = arguments;
-                let cmd = match cmd {
-                    CommandType::Alias(alias) => {
+                // コマンド名も分割などで複数の単語になりうる
+                // 空になった場合は次の単語をコマンド名にする
+                let mut words = arguments.into_iter();
+                let mut expanded = command_name.expand(shell)?;
+                while expanded.is_empty() {
+                    match words.next() {
+                        Some(w) => expanded = w.expand(shell)?,
+                        None => break,
+                    }
+                }
+                if expanded.is_empty() {
+                    for fd in opened {
+                        unistd::close(fd).map_err(error_to_string)?;
+                    }
+                    group.done(0);
+                    return Ok(());
+                }
+                let command_name = expanded.remove(0);
+                let mut arguments: Vec<Str> =
+                    expanded.into_iter().map(Str::Raw).chain(words).collect();
+                let cmd = match command_search(shell, command_name.as_str()) {
+                    Some(CommandType::Alias(alias)) => {
                         let tmp: Vec<String> = alias.split(" ").map(str::to_string).collect();
                         if tmp.is_empty() {
                             return Err(error_to_string("empty command."));
@@ -250,110 +1047,525 @@ impl Command {
                         arguments = new_arg;
 
                         let com_name = tmp.first().unwrap();
-                        command_search(shell, com_name)?
+                        command_search(shell, com_name).ok_or_else(|| com_name.clone())
                     }
-                    _ => cmd,
+                    Some(cmd) => Ok(cmd),
+                    None => Err(command_name),
                 };
 
                 match cmd {
-                    CommandType::External(path) => {
+                    Ok(CommandType::External(path)) => {
+                        // 展開はシェル自身で行う (`${x:=word}` の代入などが残るように)
+                        let path =
+                            ffi::CString::new(path.to_str().unwrap()).map_err(error_to_string)?;
+                        let mut argv = Vec::with_capacity(1 + arguments.len());
+                        argv.push(path.clone());
+                        for a in expand_words(arguments, shell)? {
+                            argv.push(ffi::CString::new(a).map_err(error_to_string)?);
+                        }
+                        let env = child_env(shell, assignments)?;
                         match unistd::fork() {
-                            Ok(unistd::ForkResult::Parent { child, .. }) => {}
+                            Ok(unistd::ForkResult::Parent { child, .. }) => {
+                                group.join(shell, child);
+                            }
                             Ok(unistd::ForkResult::Child) => {
+                                group.enter(shell);
                                 for fd in to_close {
                                     unistd::close(fd).map_err(error_then_exit).unwrap();
                                 }
 
-                                unistd::dup2(stdin, 0).unwrap();
-                                unistd::dup2(stdout, 1).unwrap();
-                                unistd::dup2(stderr, 2).unwrap();
-
-                                let path = ffi::CString::new(path.to_str().unwrap()).unwrap();
-                                let mut argv = Vec::with_capacity(1 + arguments.len());
-                                argv.push(path.clone());
-                                let mut arg_str = Vec::new();
-                                for a in arguments {
-                                    let tmp = a.extract(shell).map_err(error_then_exit).unwrap();
-                                    let cs =
-                                        ffi::CString::new(tmp).map_err(error_then_exit).unwrap();
-                                    arg_str.push(cs);
-                                }
-                                argv.append(&mut arg_str);
-                                // eprintln!("path: {:?}, in: {}, out: {}", path, stdin, stdout);
+                                fds.install().map_err(error_then_exit).unwrap();
 
-                                unistd::execv(&path, &argv).map_err(error_then_exit);
+                                // 戻ってきた場合は実行できなかった
+                                let e = unistd::execve(&path, &argv, &env).err().unwrap();
+                                eprintln!("error: `{}`: {}", path.to_string_lossy(), e);
+                                let exit_code = match e {
+                                    nix::Error::Sys(nix::errno::Errno::ENOENT) => 127,
+                                    _ => 126,
+                                };
+                                std::process::exit(exit_code);
                             }
                             _ => return Err(error_to_string(format!("fork failed."))),
                         }
                     }
-                    CommandType::Builtin(f) => {
-                        let args = arguments
-                            .iter()
-                            .map(|a| a.clone().extract(shell).unwrap())
-                            .collect::<Vec<_>>();
-
-                        let old_stdin = unistd::dup(0).map_err(error_to_string)?;
-                        let old_stdout = unistd::dup(1).map_err(error_to_string)?;
-                        let old_stderr = unistd::dup(2).map_err(error_to_string)?;
-
-                        // eprintln!("builtin `{:?}`, in: {}, out: {}", command_name, stdin, stdout);
-
-                        unistd::dup2(stdin, 0).map_err(error_to_string)?;
-                        unistd::dup2(stdout, 1).map_err(error_to_string)?;
-                        unistd::dup2(stderr, 2).map_err(error_to_string)?;
-
-                        f(shell, args);
-
-                        unistd::dup2(old_stdin, 0).map_err(error_to_string)?;
-                        unistd::dup2(old_stdout, 1).map_err(error_to_string)?;
-                        unistd::dup2(old_stderr, 2).map_err(error_to_string)?;
-
-                        unistd::close(old_stdin).map_err(error_to_string)?;
-                        unistd::close(old_stdout).map_err(error_to_string)?;
-                        unistd::close(old_stderr).map_err(error_to_string)?;
+                    Ok(CommandType::Builtin(f)) => {
+                        // パイプラインの途中では fork するので、`cd` などはシェル自身に影響しない
+                        let args = expand_words(arguments, shell)?;
+                        exec_internal(shell, &fds, to_close, group, |shell| {
+                            let saved = assign_env(shell, assignments)?;
+                            let exit_code = f(shell, args);
+                            restore_env(saved);
+                            Ok(exit_code)
+                        })?;
+                    }
+                    Ok(CommandType::Function(body)) => {
+                        let args = expand_words(arguments, shell)?;
+                        exec_internal(shell, &fds, to_close, group, |shell| {
+                            let saved = assign_env(shell, assignments)?;
+                            let exit_code = shell.call_function(body, args);
+                            restore_env(saved);
+                            exit_code
+                        })?;
+                    }
+                    Ok(CommandType::Alias(_)) => {}
+                    Err(name) => {
+                        let msg = format!("error: `{}` not found.\n", name);
+                        if let Some(stderr) = fds.get(2) {
+                            let _ = unistd::write(stderr, msg.as_bytes());
+                        }
+                        group.done(127);
                     }
-                    _ => {}
                 }
             }
             Executable::SubShell(cmds) => {
                 // 新しいシェルでcmdsを実行
                 let mut child_shell = Shell::fromParent(&shell);
                 match unistd::fork() {
-                    Ok(unistd::ForkResult::Parent { child, .. }) => {}
+                    Ok(unistd::ForkResult::Parent { child, .. }) => {
+                        group.join(&shell, child);
+                    }
                     Ok(unistd::ForkResult::Child) => {
+                        group.enter(&shell);
                         for fd in to_close {
                             unistd::close(fd).map_err(error_then_exit).unwrap();
                         }
 
-                        unistd::dup2(stdin, 0).map_err(error_then_exit);
-                        unistd::dup2(stdout, 1).map_err(error_then_exit);
-                        unistd::dup2(stderr, 2).map_err(error_then_exit);
-                        child_shell.exec(cmds).map_err(error_then_exit);
-                        std::process::exit(0);
+                        fds.install().map_err(error_then_exit).unwrap();
+                        let exit_code = child_shell.exec(cmds).map_err(error_then_exit).unwrap();
+                        std::process::exit(exit_code);
                     }
                     _ => return Err(error_to_string(format!("fork failed."))),
                 }
             }
+            compound => {
+                exec_internal(shell, &fds, to_close, group, |shell| compound.exec(shell))?;
+            }
+        }
+        for fd in opened {
+            unistd::close(fd).map_err(error_to_string)?;
         }
         Ok(())
     }
 }
 
+// 外部コマンドに渡す環境変数 (`NAME=value` の形)
+// シェルの環境変数にコマンドの前に書いた代入を加えたもので、シェル自身の環境は変えない
+fn child_env(
+    shell: &mut Shell,
+    assignments: Vec<(String, Str)>,
+) -> Result<Vec<ffi::CString>, String> {
+    let mut env: Vec<(ffi::OsString, ffi::OsString)> = std::env::vars_os().collect();
+    for (name, value) in assignments {
+        let value = value.extract(shell)?;
+        env.retain(|(k, _)| k.as_os_str() != name.as_str());
+        env.push((name.into(), value.into()));
+    }
+    env.into_iter()
+        .map(|(name, value)| {
+            let mut entry = name.into_vec();
+            entry.push(b'=');
+            entry.extend(value.into_vec());
+            ffi::CString::new(entry).map_err(error_to_string)
+        })
+        .collect()
+}
+
+// コマンドの前に書いた代入を環境変数に設定し、元に戻すための元の値を返す
+// 組み込みコマンドと関数はシェル自身の中で実行するので、終わったら restore_env で戻す
+fn assign_env(
+    shell: &mut Shell,
+    assignments: Vec<(String, Str)>,
+) -> Result<Vec<(String, Option<ffi::OsString>)>, String> {
+    let mut values = Vec::with_capacity(assignments.len());
+    for (name, value) in assignments {
+        values.push((name, value.extract(shell)?));
+    }
+    let mut saved = Vec::with_capacity(values.len());
+    for (name, value) in values {
+        saved.push((name.clone(), std::env::var_os(&name)));
+        std::env::set_var(name, value);
+    }
+    Ok(saved)
+}
+
+fn restore_env(saved: Vec<(String, Option<ffi::OsString>)>) {
+    for (name, old) in saved.into_iter().rev() {
+        match old {
+            Some(value) => std::env::set_var(name, value),
+            None => std::env::remove_var(name),
+        }
+    }
+}
+
+// 組み込みコマンドや複合コマンド、関数のようにシェル自身が解釈するコマンドを実行する
+// パイプラインの途中では他のコマンドと並行して動くように fork する
+fn exec_internal<F>(
+    shell: &mut Shell,
+    fds: &FdMap,
+    to_close: Vec<io::RawFd>,
+    group: &mut ProcessGroup,
+    f: F,
+) -> Result<(), String>
+where
+    F: FnOnce(&mut Shell) -> Result<ExitCode, String>,
+{
+    if !group.pipelined {
+        let exit_code = exec_in_shell(shell, fds, f)?;
+        group.done(exit_code);
+        return Ok(());
+    }
+    match unistd::fork() {
+        Ok(unistd::ForkResult::Parent { child, .. }) => {
+            group.join(shell, child);
+            Ok(())
+        }
+        Ok(unistd::ForkResult::Child) => {
+            group.enter(shell);
+            shell.reset_for_subshell();
+            for fd in to_close {
+                unistd::close(fd).map_err(error_then_exit).unwrap();
+            }
+
+            fds.install().map_err(error_then_exit).unwrap();
+            let exit_code = f(shell).map_err(error_then_exit).unwrap();
+            let _ = std::io::stdout().flush();
+            std::process::exit(exit_code);
+        }
+        _ => Err(error_to_string("fork failed.")),
+    }
+}
+
+// シェル自身のファイルディスクリプタを一時的に差し替えて f を実行する
+fn exec_in_shell<F>(shell: &mut Shell, fds: &FdMap, f: F) -> Result<ExitCode, String>
+where
+    F: FnOnce(&mut Shell) -> Result<ExitCode, String>,
+{
+    // 差し替える前の fd を退避しておく (閉じていたものは None)
+    let base = fds.base();
+    let saved: Vec<(io::RawFd, Option<io::RawFd>)> = fds
+        .0
+        .keys()
+        .map(|&fd| {
+            (
+                fd,
+                fcntl::fcntl(fd, fcntl::FcntlArg::F_DUPFD_CLOEXEC(base)).ok(),
+            )
+        })
+        .collect();
+
+    let result = fds.install().and_then(|_| f(shell));
+    // バッファに残っている出力は差し替えた先に書き出す
+    let _ = std::io::stdout().flush();
+
+    for (fd, old) in saved {
+        match old {
+            Some(old) => {
+                unistd::dup2(old, fd).map_err(error_to_string)?;
+                unistd::close(old).map_err(error_to_string)?;
+            }
+            None => {
+                let _ = unistd::close(fd);
+            }
+        }
+    }
+    result
+}
+
+impl CondExpr {
+    // `[[ ... ]]` の条件を評価する
+    fn eval(self, shell: &mut Shell) -> Result<bool, String> {
+        use super::builtin_commands::{binary_test, unary_test};
+        match self {
+            CondExpr::Not(e) => Ok(!e.eval(shell)?),
+            CondExpr::And(a, b) => Ok(a.eval(shell)? && b.eval(shell)?),
+            CondExpr::Or(a, b) => Ok(a.eval(shell)? || b.eval(shell)?),
+            CondExpr::Word(w) => Ok(!word::literal(shell, w)?.is_empty()),
+            CondExpr::Unary(op, w) => {
+                let arg = word::literal(shell, w)?;
+                unary_test(shell, &op, &arg)
+            }
+            // 右辺はパターン (クォートした部分は普通の文字)
+            CondExpr::Binary(op, a, b) if ["=", "==", "!="].contains(&op.as_str()) => {
+                let text = word::literal(shell, a)?;
+                let matched = pattern::matches(&b.extract_pattern(shell)?, &text);
+                Ok(matched == (op != "!="))
+            }
+            // 整数の比較では両辺を算術式として計算する
+            CondExpr::Binary(op, a, b)
+                if ["-eq", "-ne", "-lt", "-le", "-gt", "-ge"].contains(&op.as_str()) =>
+            {
+                let a = word::literal(shell, a)?;
+                let a = arithmetic::eval(shell, &a)?.to_string();
+                let b = word::literal(shell, b)?;
+                let b = arithmetic::eval(shell, &b)?.to_string();
+                binary_test(&op, &a, &b)
+            }
+            CondExpr::Binary(op, a, b) => {
+                let a = word::literal(shell, a)?;
+                let b = word::literal(shell, b)?;
+                binary_test(&op, &a, &b)
+            }
+            // 一致したら、全体と各括弧に一致した部分を配列 MATCH に設定する
+            CondExpr::Regex(a, parts) => {
+                let text = word::literal(shell, a)?;
+                let mut pattern = String::new();
+                for part in parts {
+                    match part {
+                        quoted @ Str::Quoted(_) => {
+                            pattern.push_str(&regex::escape(&quoted.extract(shell)?))
+                        }
+                        part => pattern.push_str(&part.extract(shell)?),
+                    }
+                }
+                match regex::search(&pattern, &text)? {
+                    Some(groups) => {
+                        shell.set_array("MATCH".to_string(), groups);
+                        Ok(true)
+                    }
+                    None => {
+                        shell.set_array("MATCH".to_string(), Vec::new());
+                        Ok(false)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Executable {
+    // 複合コマンドをシェル自身で実行する
+    fn exec(self, shell: &mut Shell) -> Result<ExitCode, String> {
+        match self {
+            Executable::Group(list) => shell.exec(list),
+            Executable::Conditional(e) => Ok(if e.eval(shell)? { 0 } else { 1 }),
+            Executable::If {
+                conditions,
+                otherwise,
+            } => {
+                for (condition, body) in conditions {
+                    let status = shell.exec(condition)?;
+                    if shell.flow.is_some() {
+                        return Ok(status);
+                    }
+                    if status == 0 {
+                        return shell.exec(body);
+                    }
+                }
+                match otherwise {
+                    Some(list) => shell.exec(list),
+                    None => Ok(0),
+                }
+            }
+            Executable::While {
+                condition,
+                body,
+                until,
+            } => {
+                shell.loop_depth += 1;
+                let result = exec_while(shell, condition, body, until);
+                shell.loop_depth -= 1;
+                result
+            }
+            Executable::For {
+                variable,
+                words,
+                body,
+            } => {
+                let words = match words {
+                    Some(words) => expand_words(words, shell)?,
+                    None => shell.positional.clone(),
+                };
+                shell.loop_depth += 1;
+                let result = exec_for(shell, variable, words, body);
+                shell.loop_depth -= 1;
+                result
+            }
+            Executable::Case { word, arms } => {
+                let word = word.extract(shell)?;
+                for CaseArm { patterns, body } in arms {
+                    for p in patterns {
+                        if pattern::matches(&p.extract_pattern(shell)?, &word) {
+                            return match body {
+                                Some(list) => shell.exec(list),
+                                None => Ok(0),
+                            };
+                        }
+                    }
+                }
+                Ok(0)
+            }
+            Executable::FunctionDef { name, body } => {
+                shell
+                    .command_table
+                    .insert(name, CommandType::Function(body));
+                Ok(0)
+            }
+            Executable::Arithmetic(expr) => {
+                let expr = Str::Quoted(expr).extract(shell)?;
+                // 結果が 0 なら失敗
+                if arithmetic::eval(shell, &expr)? != 0 {
+                    Ok(0)
+                } else {
+                    Ok(1)
+                }
+            }
+            Executable::Assignment(assignments) => {
+                for (name, value) in assignments {
+                    let value = value.extract(shell)?;
+                    shell.assign_variable(name, value);
+                }
+                Ok(0)
+            }
+            Executable::File { .. } | Executable::SubShell(_) => {
+                unreachable!("not a compound command")
+            }
+        }
+    }
+}
+
+// ループの本体を実行した後に break/continue を処理する
+// ループを抜けるなら true を返す
+fn leave_loop(shell: &mut Shell) -> bool {
+    match shell.flow.take() {
+        Some(Flow::Break(n)) => {
+            if n > 1 {
+                shell.flow = Some(Flow::Break(n - 1));
+            }
+            true
+        }
+        Some(Flow::Continue(n)) => {
+            if n > 1 {
+                shell.flow = Some(Flow::Continue(n - 1));
+                return true;
+            }
+            false
+        }
+        // return と割り込みはループの外まで伝える
+        Some(flow @ Flow::Return) | Some(flow @ Flow::Interrupt) => {
+            shell.flow = Some(flow);
+            true
+        }
+        None => false,
+    }
+}
+
+fn exec_while(
+    shell: &mut Shell,
+    condition: List,
+    body: List,
+    until: bool,
+) -> Result<ExitCode, String> {
+    let mut exit_status = 0;
+    loop {
+        let status = shell.exec(condition.clone())?;
+        if leave_loop(shell) {
+            break;
+        }
+        if (status == 0) == until {
+            break;
+        }
+        exit_status = shell.exec(body.clone())?;
+        if leave_loop(shell) {
+            break;
+        }
+    }
+    Ok(exit_status)
+}
+
+fn exec_for(
+    shell: &mut Shell,
+    variable: String,
+    words: Vec<String>,
+    body: List,
+) -> Result<ExitCode, String> {
+    let mut exit_status = 0;
+    for w in words {
+        shell.set_variable(variable.clone(), w);
+        exit_status = shell.exec(body.clone())?;
+        if leave_loop(shell) {
+            break;
+        }
+    }
+    Ok(exit_status)
+}
+
+// 単語の並びを展開して引数の並びにする
+fn expand_words(words: Vec<Str>, shell: &mut Shell) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for w in words {
+        expanded.append(&mut w.expand(shell)?);
+    }
+    Ok(expanded)
+}
+
 impl Str {
+    // パターンとして使う文字列にする (クォートされた部分は特別な意味を持たない)
+    pub fn extract_pattern(self, shell: &mut Shell) -> Result<String, String> {
+        match self {
+            Str::Raw(s) => Ok(pattern::escape(&s)),
+            Str::Glob(p) => Ok(p),
+            quoted @ Str::Quoted(_) => Ok(pattern::escape(&quoted.extract(shell)?)),
+            Str::Word(parts) => {
+                let mut p = String::new();
+                for part in parts {
+                    p.push_str(&part.extract_pattern(shell)?);
+                }
+                Ok(p)
+            }
+            s => s.extract(shell),
+        }
+    }
+
+    // コマンドの引数として展開する (分割やパス名展開で複数の引数になることがある)
+    pub fn expand(self, shell: &mut Shell) -> Result<Vec<String>, String> {
+        word::expand(shell, self)
+    }
+
+    // クォートされていない文字の並び (パス名展開の文字を含めばパターンにする)
+    pub fn unquoted(s: &str) -> Str {
+        if pattern::has_meta(s) {
+            Str::Glob(s.to_string())
+        } else {
+            Str::Raw(s.to_string())
+        }
+    }
+
+    // 代入する値のようにパス名展開をしない場所では、パターンを普通の文字として扱う
+    pub fn without_glob(self) -> Str {
+        match self {
+            Str::Glob(p) => Str::Raw(pattern::unescape(&p)),
+            Str::Word(parts) => Str::Word(parts.into_iter().map(Str::without_glob).collect()),
+            s => s,
+        }
+    }
+
     pub fn extract(self, shell: &mut Shell) -> Result<String, String> {
         match self {
             Str::Raw(s) => Ok(s.clone()),
+            // 引数以外 (リダイレクト先など) では一致するものが1つの場合だけ展開する
+            Str::Glob(p) => word::extract(shell, vec![Str::Glob(p)]),
+            Str::Word(parts) => word::extract(shell, parts),
+            Str::Tilde(user) => Ok(word::tilde(&user)),
+            brace @ Str::Brace(_) | brace @ Str::Sequence(..) => word::extract(shell, vec![brace]),
             Str::Variable(v) => {
+                if let Some(value) = shell.special_variable(v.as_str()) {
+                    return Ok(value);
+                }
                 match std::env::var(v.as_str()) {
                     Ok(v) => return Ok(v),
                     _ => {}
                 };
-                match shell.variables.get(v.as_str()) {
-                    Some(v) => return Ok(v.clone()),
-                    _ => {}
-                };
+                if let Some(v) = shell.get_variable(v.as_str()) {
+                    return Ok(v);
+                }
                 Ok("".to_string()) // 未定義なら空文字列を返す
             }
+            Str::Parameter(name, op) => parameter::expand(shell, &name, op),
+            Str::Element(name, index) => parameter::element(shell, &name, index),
+            Str::Arithmetic(expr) => {
+                let expr = Str::Quoted(expr).extract(shell)?;
+                Ok(arithmetic::eval(shell, &expr)?.to_string())
+            }
             Str::SubShellResult(list) => {
                 // pipeを作成して、in側をopenしてバッファに書き込む
                 // これを文字列として返す
@@ -0,0 +1,207 @@
+use super::completion;
+use super::line_editor::{Completer, LineEditor, ReadResult};
+use super::msh_grammar;
+use super::prompt;
+use super::structures::*;
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+// コマンドを読み込む入力元 (端末、スクリプトファイル、`-c`で渡された文字列など)
+pub struct Input {
+    reader: Box<dyn BufRead>,
+    editor: Option<LineEditor>, // 端末から読む場合の行エディタ
+    interactive: bool,          // プロンプトを表示するか
+    interrupted: bool,          // Ctrl-C で入力が取り消された
+    heredocs: VecDeque<String>, // 読み込み済みで、まだコマンドに渡していないヒアドキュメントの本文
+}
+
+impl Input {
+    pub fn interactive() -> Input {
+        Input {
+            reader: Box::new(io::BufReader::new(io::stdin())),
+            editor: Some(LineEditor::new()),
+            interactive: true,
+            interrupted: false,
+            heredocs: VecDeque::new(),
+        }
+    }
+    pub fn from_reader<R: BufRead + 'static>(reader: R) -> Input {
+        Input {
+            reader: Box::new(reader),
+            editor: None,
+            interactive: false,
+            interrupted: false,
+            heredocs: VecDeque::new(),
+        }
+    }
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    // 1行読み込む (改行を含む)
+    // 入力の終わりに達したら None を返す
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        self.read_line_with(prompt, None)
+    }
+
+    // 行エディタで読む場合は completer で Tab キーによる補完をする
+    fn read_line_with(
+        &mut self,
+        prompt: &str,
+        completer: Option<&mut Completer<'_>>,
+    ) -> Option<String> {
+        if let Some(editor) = &mut self.editor {
+            return match editor.read_line(prompt, completer) {
+                ReadResult::Line(line) => Some(line + "\n"),
+                ReadResult::Interrupted => {
+                    self.interrupted = true;
+                    Some("\n".to_string())
+                }
+                ReadResult::Eof => None,
+            };
+        }
+        if self.interactive {
+            print!("{}", prompt);
+            io::stdout().flush().unwrap();
+        }
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+
+    // コマンドの1行を読み込む
+    // 行の中に `<<` があれば、続くヒアドキュメントの本文もここで読んでおく
+    // (if や while の途中でもコマンドの続きより先に本文が来るため)
+    pub fn read_command_line(&mut self, prompt: &str, shell: &mut Shell) -> Option<String> {
+        let line =
+            self.read_line_with(prompt, Some(&mut |line| completion::complete(shell, line)))?;
+        if self.interrupted {
+            return Some(line);
+        }
+        if let Some(editor) = &mut self.editor {
+            editor.history.add(&line);
+        }
+        for (delimiter, strip_tabs) in heredoc_delimiters(&line) {
+            let mut body = String::new();
+            let continuation = if self.interactive {
+                prompt::expand_variable(shell, "PS2", prompt::DEFAULT_PS2)
+            } else {
+                String::new()
+            };
+            while let Some(line) = self.read_line(&continuation) {
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line.as_str()
+                };
+                if line.trim_end_matches('\n') == delimiter {
+                    break;
+                }
+                body.push_str(line);
+            }
+            self.heredocs.push_back(body);
+        }
+        Some(line)
+    }
+
+    // Ctrl-C で入力が取り消されたか (確認するとリセットされる)
+    pub fn take_interrupted(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupted, false)
+    }
+
+    // 構文エラーなどで使われなかったヒアドキュメントを捨てる
+    pub fn discard_heredocs(&mut self) {
+        self.heredocs.clear();
+    }
+
+    // 読み込み済みのヒアドキュメントの本文を、コマンドに順番に割り当てる
+    pub fn read_heredocs(&mut self, list: &mut List) {
+        for doc in list.heredocs_mut() {
+            let body = self.heredocs.pop_front().unwrap_or_default();
+            doc.body = if doc.quoted {
+                Str::Raw(body)
+            } else {
+                // 本文の解析は文法のモードによらない
+                match msh_grammar::heredoc_body(&body, false) {
+                    Ok(parts) => Str::Quoted(parts),
+                    Err(_) => Str::Raw(body),
+                }
+            };
+        }
+        self.heredocs.clear();
+    }
+}
+
+// 1行に含まれるヒアドキュメントの (区切り文字, 先頭のタブを取り除くか) を順に返す
+// クォートの中やコメントの `<<` は無視する
+fn heredoc_delimiters(line: &str) -> Vec<(String, bool)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut delimiters = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            q @ '\'' | q @ '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != q {
+                    if q == '"' && chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            '#' if i == 0 || chars[i - 1].is_whitespace() => break,
+            // `((...))` と `$((...))` の中の `<<` はシフト演算子
+            '(' if chars.get(i + 1) == Some(&'(') => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            '<' if chars.get(i + 1) == Some(&'<') => {
+                i += 2;
+                let strip_tabs = chars.get(i) == Some(&'-');
+                if strip_tabs {
+                    i += 1;
+                }
+                while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+                    i += 1;
+                }
+                let mut delimiter = String::new();
+                match chars.get(i) {
+                    Some(&q) if q == '\'' || q == '"' => {
+                        i += 1;
+                        while i < chars.len() && chars[i] != q {
+                            delimiter.push(chars[i]);
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    _ => {
+                        while i < chars.len() && !" \t\n;&()|<>".contains(chars[i]) {
+                            delimiter.push(chars[i]);
+                            i += 1;
+                        }
+                    }
+                }
+                delimiters.push((delimiter, strip_tabs));
+            }
+            _ => i += 1,
+        }
+    }
+    delimiters
+}
@@ -0,0 +1,668 @@
+// 端末から1行を読み込むための行エディタ
+// 端末を raw モードにして1文字ずつ読み、emacs 風のキー操作と履歴を扱う
+
+use nix::sys::termios;
+use nix::unistd;
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+// Tab キーで呼ばれる補完の処理
+// カーソルより前の行を受け取り、置き換える単語の開始位置 (文字数) と候補を返す
+pub type Completer<'a> = dyn FnMut(&str) -> (usize, Vec<String>) + 'a;
+
+// プロンプトの中でこの2つに囲まれた部分 (色を変える制御文字列など) は幅を持たないものとして扱う
+pub const IGNORE_START: char = '\x01';
+pub const IGNORE_END: char = '\x02';
+
+// 読み込みの結果
+pub enum ReadResult {
+    Line(String), // 入力された行 (改行は含まない)
+    Interrupted,  // Ctrl-C で入力が取り消された
+    Eof,          // 空の行で Ctrl-D が押された
+}
+
+// 入力されたキー
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char), // Ctrl と英字 (小文字で表す)
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    AltBackspace,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Unknown,
+}
+
+// コマンドの履歴 (~/.msh_history に保存する)
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    max_size: usize, // $HISTSIZE で変えられる
+}
+
+impl History {
+    pub fn load() -> History {
+        let path = std::env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(".msh_history"));
+        let max_size = std::env::var("HISTSIZE")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_SIZE);
+        let mut history = History {
+            entries: Vec::new(),
+            path,
+            max_size,
+        };
+        if let Some(content) = history
+            .path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+        {
+            for line in content.lines() {
+                history.push(line.to_string());
+            }
+        }
+        history
+    }
+
+    // 同じ内容の古い履歴は取り除いて末尾に追加する
+    fn push(&mut self, line: String) {
+        if line.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|e| *e != line);
+        self.entries.push(line);
+        if self.entries.len() > self.max_size {
+            let excess = self.entries.len() - self.max_size;
+            self.entries.drain(..excess);
+        }
+    }
+
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim_end_matches('\n');
+        if line.trim().is_empty() || line.contains('\n') {
+            return;
+        }
+        self.push(line.to_string());
+        self.save();
+    }
+
+    // `exit` でそのまま終了しても残るように追加するたびに書き出す
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            let mut content = self.entries.join("\n");
+            content.push('\n');
+            let _ = fs::write(path, content);
+        }
+    }
+}
+
+// 端末を raw モードにしている間だけ存在する
+struct RawMode {
+    original: termios::Termios,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let original = termios::tcgetattr(0).ok()?;
+        let mut raw = original.clone();
+        raw.local_flags.remove(
+            termios::LocalFlags::ICANON
+                | termios::LocalFlags::ECHO
+                | termios::LocalFlags::ISIG
+                | termios::LocalFlags::IEXTEN,
+        );
+        raw.input_flags
+            .remove(termios::InputFlags::ICRNL | termios::InputFlags::IXON);
+        raw.control_chars[termios::SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[termios::SpecialCharacterIndices::VTIME as usize] = 0;
+        termios::tcsetattr(0, termios::SetArg::TCSADRAIN, &raw).ok()?;
+        Some(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(0, termios::SetArg::TCSADRAIN, &self.original);
+    }
+}
+
+pub struct LineEditor {
+    pub history: History,
+    buffer: Vec<char>,
+    cursor: usize,      // buffer 中のカーソル位置
+    cursor_row: usize,  // 前回表示したときのカーソルの行 (プロンプトの行から数える)
+    kill_ring: String,  // Ctrl-K などで消した文字列 (Ctrl-Y で貼り付ける)
+    history_pos: usize, // 表示している履歴の位置 (entries.len() なら新しい行)
+    editing: Vec<char>, // 履歴をたどる前に入力していた行
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor {
+            history: History::load(),
+            buffer: Vec::new(),
+            cursor: 0,
+            cursor_row: 0,
+            kill_ring: String::new(),
+            history_pos: 0,
+            editing: Vec::new(),
+        }
+    }
+
+    // completer が無い場合は Tab キーでタブ文字を入力する
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        mut completer: Option<&mut Completer<'_>>,
+    ) -> ReadResult {
+        let _raw = match RawMode::enable() {
+            Some(raw) => raw,
+            None => return read_line_cooked(prompt),
+        };
+        // 複数行のプロンプトは最後の行以外を先に出力し、表示し直すのは最後の行だけにする
+        let prompt = match prompt.rfind('\n') {
+            Some(i) => {
+                write_out(&visible(&prompt[..=i]).replace('\n', "\r\n"));
+                &prompt[i + 1..]
+            }
+            None => prompt,
+        };
+        self.buffer.clear();
+        self.cursor = 0;
+        self.cursor_row = 0;
+        self.history_pos = self.history.entries.len();
+        self.editing.clear();
+        self.refresh(prompt);
+
+        loop {
+            let key = match read_key() {
+                Some(key) => key,
+                None => return ReadResult::Eof,
+            };
+            let key = if key == Key::Ctrl('r') {
+                match self.reverse_search(prompt) {
+                    Some(key) => key,
+                    None => {
+                        self.refresh(prompt);
+                        continue;
+                    }
+                }
+            } else {
+                key
+            };
+            match key {
+                Key::Enter => {
+                    self.cursor = self.buffer.len();
+                    self.refresh(prompt);
+                    write_out("\r\n");
+                    return ReadResult::Line(self.buffer.iter().collect());
+                }
+                Key::Ctrl('c') => {
+                    self.cursor = self.buffer.len();
+                    self.refresh(prompt);
+                    write_out("^C\r\n");
+                    return ReadResult::Interrupted;
+                }
+                Key::Ctrl('d') if self.buffer.is_empty() => {
+                    write_out("\r\n");
+                    return ReadResult::Eof;
+                }
+                Key::Ctrl('l') => {
+                    write_out("\x1b[H\x1b[2J");
+                    self.cursor_row = 0;
+                }
+                Key::Tab => match &mut completer {
+                    Some(completer) => self.complete(prompt, completer),
+                    None => self.edit(Key::Char('\t')),
+                },
+                key => self.edit(key),
+            }
+            self.refresh(prompt);
+        }
+    }
+
+    // 行を書き換えるキー操作
+    fn edit(&mut self, key: Key) {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Ctrl('a') | Key::Home => self.cursor = 0,
+            Key::Ctrl('e') | Key::End => self.cursor = self.buffer.len(),
+            Key::Ctrl('b') | Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Ctrl('f') | Key::Right => {
+                self.cursor = (self.cursor + 1).min(self.buffer.len());
+            }
+            Key::Alt('b') => self.cursor = self.word_start(),
+            Key::Alt('f') => self.cursor = self.word_end(),
+            Key::Ctrl('h') | Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buffer.remove(self.cursor);
+            }
+            Key::Ctrl('d') | Key::Delete if self.cursor < self.buffer.len() => {
+                self.buffer.remove(self.cursor);
+            }
+            Key::Ctrl('k') => self.kill(self.cursor, self.buffer.len()),
+            Key::Ctrl('u') => self.kill(0, self.cursor),
+            Key::Ctrl('w') | Key::AltBackspace => self.kill(self.word_start(), self.cursor),
+            Key::Alt('d') => self.kill(self.cursor, self.word_end()),
+            Key::Ctrl('y') => {
+                let yank: Vec<char> = self.kill_ring.chars().collect();
+                let n = yank.len();
+                self.buffer.splice(self.cursor..self.cursor, yank);
+                self.cursor += n;
+            }
+            // カーソルの前の2文字を入れ替える
+            Key::Ctrl('t') if self.cursor > 0 && self.buffer.len() >= 2 => {
+                if self.cursor == self.buffer.len() {
+                    self.cursor -= 1;
+                }
+                self.buffer.swap(self.cursor - 1, self.cursor);
+                self.cursor += 1;
+            }
+            Key::Ctrl('p') | Key::Up => self.history_move(-1),
+            Key::Ctrl('n') | Key::Down => self.history_move(1),
+            _ => {}
+        }
+    }
+
+    // カーソル位置の単語を補完する
+    // 候補が1つならそれに置き換え、複数なら共通部分まで補完して、
+    // それ以上補完できなければ候補を一覧表示する
+    fn complete(&mut self, prompt: &str, completer: &mut Completer<'_>) {
+        let before: String = self.buffer[..self.cursor].iter().collect();
+        let (start, candidates) = completer(&before);
+        let start = start.min(self.cursor);
+        let replacement: Vec<char> = match candidates.len() {
+            0 => return,
+            1 => {
+                let mut c: Vec<char> = candidates[0].chars().collect();
+                if !candidates[0].ends_with('/') {
+                    c.push(' ');
+                }
+                c
+            }
+            _ => common_prefix(&candidates).chars().collect(),
+        };
+
+        if candidates.len() > 1 && replacement.len() <= self.cursor - start {
+            self.list_candidates(&candidates);
+            self.refresh(prompt);
+            return;
+        }
+        let n = replacement.len();
+        self.buffer.splice(start..self.cursor, replacement);
+        self.cursor = start + n;
+    }
+
+    // 候補を編集中の行の下に並べて表示する
+    fn list_candidates(&mut self, candidates: &[String]) {
+        // パスは最後の要素だけを表示する
+        let names: Vec<String> = candidates
+            .iter()
+            .map(|c| {
+                let trimmed = c.trim_end_matches('/');
+                let name = &trimmed[trimmed.rfind('/').map_or(0, |i| i + 1)..];
+                if c.ends_with('/') && !trimmed.is_empty() {
+                    format!("{}/", name)
+                } else {
+                    name.to_string()
+                }
+            })
+            .collect();
+        let width = names.iter().map(|n| display_width(n)).max().unwrap_or(0) + 2;
+        let per_row = (terminal_width() / width).max(1);
+        let rows = names.len().div_ceil(per_row);
+
+        let mut out = String::new();
+        self.cursor = self.buffer.len();
+        out.push_str("\r\n");
+        for row in 0..rows {
+            for col in 0..per_row {
+                // 縦に並べる
+                if let Some(name) = names.get(col * rows + row) {
+                    out.push_str(name);
+                    out.push_str(&" ".repeat(width - display_width(name)));
+                }
+            }
+            out.push_str("\r\n");
+        }
+        write_out(&out);
+        self.cursor_row = 0;
+    }
+
+    fn kill(&mut self, from: usize, to: usize) {
+        if from >= to {
+            return;
+        }
+        self.kill_ring = self.buffer.drain(from..to).collect();
+        self.cursor = from;
+    }
+
+    // カーソルの前にある単語の先頭
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.buffer[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.buffer[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+    // カーソルの後にある単語の末尾
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.buffer.len() && !self.buffer[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.buffer.len() && self.buffer[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    // 履歴を前 (-1) か後 (1) に移動する
+    fn history_move(&mut self, delta: isize) {
+        let len = self.history.entries.len();
+        let pos = self.history_pos as isize + delta;
+        if pos < 0 || pos > len as isize {
+            return;
+        }
+        if self.history_pos == len {
+            self.editing = self.buffer.clone();
+        }
+        self.history_pos = pos as usize;
+        self.buffer = if self.history_pos == len {
+            self.editing.clone()
+        } else {
+            self.history.entries[self.history_pos].chars().collect()
+        };
+        self.cursor = self.buffer.len();
+    }
+
+    // Ctrl-R による履歴の逆方向インクリメンタル検索
+    // 検索を終えたキーを返す (Enter なら見つけた行をそのまま実行する)
+    fn reverse_search(&mut self, prompt: &str) -> Option<Key> {
+        let original = (self.buffer.clone(), self.cursor);
+        let mut query = String::new();
+        let mut found = self.history.entries.len();
+        let mut failed = false;
+        loop {
+            let label = format!(
+                "({}reverse-i-search)`{}': ",
+                if failed { "failed " } else { "" },
+                query
+            );
+            self.refresh(&label);
+
+            let key = read_key()?;
+            let start = match key {
+                Key::Char(c) => {
+                    query.push(c);
+                    found.min(self.history.entries.len().saturating_sub(1)) + 1
+                }
+                Key::Backspace | Key::Ctrl('h') => {
+                    query.pop();
+                    self.history.entries.len()
+                }
+                Key::Ctrl('r') => found,
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    self.buffer = original.0;
+                    self.cursor = original.1;
+                    return None;
+                }
+                key => {
+                    self.refresh(prompt);
+                    return Some(key);
+                }
+            };
+
+            // start より前で query を含む最も新しい履歴を探す
+            let hit = self.history.entries[..start.min(self.history.entries.len())]
+                .iter()
+                .rposition(|e| e.contains(query.as_str()));
+            match hit {
+                Some(i) => {
+                    failed = false;
+                    found = i;
+                    self.history_pos = i;
+                    let entry = &self.history.entries[i];
+                    let offset = entry.find(query.as_str()).unwrap_or(0);
+                    self.buffer = entry.chars().collect();
+                    self.cursor = entry[..offset].chars().count();
+                }
+                None => failed = !query.is_empty(),
+            }
+        }
+    }
+
+    // プロンプトと編集中の行を表示し直す
+    // 端末の幅で折り返された行にも対応する
+    fn refresh(&mut self, prompt: &str) {
+        let columns = terminal_width();
+        let prompt_width = display_width(prompt);
+        let total = prompt_width + self.buffer.iter().map(|&c| char_width(c)).sum::<usize>();
+        let before_cursor = prompt_width
+            + self.buffer[..self.cursor]
+                .iter()
+                .map(|&c| char_width(c))
+                .sum::<usize>();
+
+        let mut out = String::new();
+        if self.cursor_row > 0 {
+            out.push_str(&format!("\x1b[{}A", self.cursor_row));
+        }
+        out.push_str("\r\x1b[J");
+        out.push_str(&visible(prompt));
+        out.extend(self.buffer.iter());
+        if total > 0 && total.is_multiple_of(columns) {
+            // 右端で止まっているカーソルを次の行に移す
+            out.push_str("\r\n");
+        }
+
+        let end_row = total / columns;
+        let row = before_cursor / columns;
+        let column = before_cursor % columns;
+        if end_row > row {
+            out.push_str(&format!("\x1b[{}A", end_row - row));
+        }
+        out.push('\r');
+        if column > 0 {
+            out.push_str(&format!("\x1b[{}C", column));
+        }
+        self.cursor_row = row;
+        write_out(&out);
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix: Vec<char> = candidates[0].chars().collect();
+    for c in &candidates[1..] {
+        let len = prefix
+            .iter()
+            .zip(c.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(len);
+    }
+    prefix.into_iter().collect()
+}
+
+// 端末でない場合などは普通に1行読む
+fn read_line_cooked(prompt: &str) -> ReadResult {
+    write_out(&visible(prompt));
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => ReadResult::Eof,
+        Ok(_) => ReadResult::Line(line.trim_end_matches('\n').to_string()),
+    }
+}
+
+fn write_out(s: &str) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(s.as_bytes());
+    let _ = stdout.flush();
+}
+
+fn read_byte() -> Option<u8> {
+    let mut buf = [0; 1];
+    loop {
+        match unistd::read(0, &mut buf) {
+            Ok(1) => return Some(buf[0]),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            _ => return None,
+        }
+    }
+}
+
+// 1つのキー入力を読む (エスケープシーケンスや UTF-8 の複数バイトをまとめる)
+fn read_key() -> Option<Key> {
+    let b = read_byte()?;
+    let key = match b {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f => Key::Backspace,
+        0x1b => read_escape()?,
+        1..=26 => Key::Ctrl((b'a' + b - 1) as char),
+        0..=0x1f => Key::Unknown,
+        0x80..=0xff => {
+            // UTF-8 の先頭バイトから残りのバイト数を求める
+            let len = if b >= 0xf0 {
+                4
+            } else if b >= 0xe0 {
+                3
+            } else {
+                2
+            };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+        _ => Key::Char(b as char),
+    };
+    Some(key)
+}
+
+fn read_escape() -> Option<Key> {
+    let key = match read_byte()? {
+        b'[' => {
+            let mut params = String::new();
+            loop {
+                let b = read_byte()?;
+                if (0x40..=0x7e).contains(&b) {
+                    break match (b, params.as_str()) {
+                        (b'A', _) => Key::Up,
+                        (b'B', _) => Key::Down,
+                        (b'C', _) => Key::Right,
+                        (b'D', _) => Key::Left,
+                        (b'H', _) => Key::Home,
+                        (b'F', _) => Key::End,
+                        (b'~', "1") | (b'~', "7") => Key::Home,
+                        (b'~', "4") | (b'~', "8") => Key::End,
+                        (b'~', "3") => Key::Delete,
+                        _ => Key::Unknown,
+                    };
+                }
+                params.push(b as char);
+            }
+        }
+        b'O' => match read_byte()? {
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            _ => Key::Unknown,
+        },
+        0x7f | 0x08 => Key::AltBackspace,
+        b => Key::Alt((b as char).to_ascii_lowercase()),
+    };
+    Some(key)
+}
+
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+// 端末に出力するプロンプト (幅を持たない部分の印を除く)
+fn visible(prompt: &str) -> String {
+    prompt
+        .chars()
+        .filter(|&c| c != IGNORE_START && c != IGNORE_END)
+        .collect()
+}
+
+// 端末上での表示幅 (エスケープシーケンスは幅を持たない)
+fn display_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == IGNORE_START {
+            for c in chars.by_ref() {
+                if c == IGNORE_END {
+                    break;
+                }
+            }
+            continue;
+        }
+        if c == '\x1b' {
+            // CSI シーケンス (`\x1b[...m` など) を読み飛ばす
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        width += char_width(c);
+    }
+    width
+}
+
+// 全角文字は2文字分の幅になる
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0..=0x1f | 0x7f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
@@ -1,61 +1,133 @@
+mod arithmetic;
 mod builtin_commands;
+mod completion;
 mod execute;
+mod input;
+mod line_editor;
+mod parameter;
+mod pattern;
+mod printf;
+mod prompt;
+mod regex;
 mod structures;
+mod terminfo;
+mod word;
 mod msh_grammar {
     include!(concat!(env!("OUT_DIR"), "/msh_grammar.rs"));
 }
 
-use std::io;
-use std::io::Write;
-
 use nix::sys::signal;
+use nix::unistd;
 
 extern "C" fn do_nothing(_: std::os::raw::c_int) {}
 
+fn usage() -> ! {
+    eprintln!("usage: msh [options] [script [args...]]");
+    eprintln!("       msh [options] -c command [name [args...]]");
+    eprintln!("options: -l, --login  read ~/.msh_profile as a login shell");
+    eprintln!("         --norc       do not read ~/.msh_profile and ~/.mshrc");
+    eprintln!("         --posix      parse commands with the POSIX sh syntax");
+    std::process::exit(2);
+}
+
+// 起動時に読み込むファイル (無ければ何もしない)
+fn source_startup_file(shell: &mut structures::Shell, name: &str) {
+    let home = match std::env::var("HOME") {
+        Ok(home) => home,
+        Err(_) => return,
+    };
+    let path = std::path::Path::new(&home).join(name);
+    if path.is_file() {
+        if let Err(e) = shell.run_file(&path) {
+            eprintln!("msh: {}", e);
+        }
+    }
+}
+
 fn main() {
-    println!("msh version {}", env!("CARGO_PKG_VERSION"));
-
-    // SIGINTでシェルが終了しないように設定
-    let sigign = signal::SigAction::new(
-        signal::SigHandler::Handler(do_nothing),
-        signal::SaFlags::SA_RESTART,
-        signal::SigSet::empty(),
-    );
+    // 環境変数のロケールに合わせる (`=~` の正規表現が UTF-8 などの文字単位で一致するように)
     unsafe {
-        signal::sigaction(signal::Signal::SIGINT, &sigign).unwrap();
+        libc::setlocale(libc::LC_ALL, b"\0".as_ptr() as *const std::os::raw::c_char);
     }
 
-    let mut shell = structures::Shell::new();
-    loop {
-        let user = std::env::var("USER").unwrap_or(format!("(unknown)"));
-        print!(
-            // TODO terminfo から引くべき
-            "\x1b[33;1m{}\x1b[0m@\x1b[36;1m{}\x1b[0m \x1b[1m$\x1b[0m ",
-            user,
-            std::env::current_dir().unwrap().display()
-        );
-        io::stdout().flush().unwrap();
-        let mut s = String::new();
-        let bytes = io::stdin().read_line(&mut s).unwrap();
-        if bytes == 0 {
-            break;
+    // 引数がなければ標準入力から、スクリプトのパスがあればそのファイルから、
+    // `-c`があれば続く文字列からコマンドを読む
+    // 残りの引数は $0, $1, ... になる
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // ログインシェルは $0 が `-` で始まるか、`-l`/`--login`で起動される
+    let mut login = args[0].starts_with('-');
+    let mut norc = false;
+    let mut posix = false;
+    while let Some(option) = args.get(1) {
+        match option.as_str() {
+            "-l" | "--login" => login = true,
+            "--norc" => norc = true,
+            "--posix" => posix = true,
+            _ => break,
         }
+        args.remove(1);
+    }
 
-        match msh_grammar::command_line(&s.trim()) {
-            Ok(r) => match r {
-                Some(list) => {
-                    // println!("{}\n{}\n{}", "=".repeat(80), r.print(0), "=".repeat(80));
-                    match shell.exec(list) {
-                        Ok(exit_code) => println!("=> {}", exit_code),
-                        Err(e) => println!("error: {}", e),
-                    }
-                }
-                None => {}
-            },
+    let mut input = match args.get(1).map(|a| a.as_str()) {
+        Some("-c") => match args.get(2) {
+            Some(command) => {
+                let input = input::Input::from_reader(std::io::Cursor::new(command.clone()));
+                args.drain(..3);
+                input
+            }
+            None => usage(),
+        },
+        Some(path) if path.starts_with('-') => usage(),
+        Some(path) => match std::fs::File::open(path) {
+            Ok(f) => {
+                args.drain(..1);
+                input::Input::from_reader(std::io::BufReader::new(f))
+            }
             Err(e) => {
-                println!("parsing error. {}", e);
+                eprintln!("msh: `{}`: {}", path, e);
+                std::process::exit(127);
             }
-        };
+        },
+        None if unistd::isatty(0).unwrap_or(false) => input::Input::interactive(),
+        None => input::Input::from_reader(std::io::BufReader::new(std::io::stdin())),
+    };
+
+    let mut shell = structures::Shell::new();
+    shell.options.posix = posix;
+    if !args.is_empty() {
+        shell.name = args.remove(0);
+        shell.positional = args;
     }
-    builtin_commands::exit(&mut shell, Vec::new());
+    if input.is_interactive() {
+        println!("msh version {}", env!("CARGO_PKG_VERSION"));
+
+        // SIGINTでシェルが終了しないように設定
+        let sigign = signal::SigAction::new(
+            signal::SigHandler::Handler(do_nothing),
+            signal::SaFlags::SA_RESTART,
+            signal::SigSet::empty(),
+        );
+        unsafe {
+            signal::sigaction(signal::Signal::SIGINT, &sigign).unwrap();
+        }
+
+        shell.interactive = true;
+        if let Err(e) = shell.enable_job_control() {
+            eprintln!("job control disabled: {}", e);
+        }
+    }
+
+    // ログインシェルなら ~/.msh_profile を、対話的なら ~/.mshrc を読み込む
+    if !norc {
+        if login {
+            source_startup_file(&mut shell, ".msh_profile");
+        }
+        if input.is_interactive() {
+            source_startup_file(&mut shell, ".mshrc");
+        }
+    }
+
+    let exit_code = shell.run(&mut input);
+    builtin_commands::exit(&mut shell, vec![exit_code.to_string()]);
 }
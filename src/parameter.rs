@@ -0,0 +1,292 @@
+// `${name:-word}` や `${name%pattern}` などの操作付きの変数の展開
+
+use super::pattern;
+use super::structures::*;
+
+pub fn expand(shell: &mut Shell, name: &str, op: ParamOp) -> Result<String, String> {
+    let value = lookup(shell, name);
+    // `:` 付きの操作では空文字列も未設定とみなす
+    let is_set = |colon: bool| match &value {
+        Some(v) => !colon || !v.is_empty(),
+        None => false,
+    };
+    match op {
+        ParamOp::Count => Ok(elements(shell, name).len().to_string()),
+        ParamOp::Length => {
+            let value = match name {
+                "@" | "*" => return Ok(shell.positional.len().to_string()),
+                _ => value.unwrap_or_default(),
+            };
+            Ok(value.chars().count().to_string())
+        }
+        ParamOp::Default(colon, word) => {
+            if is_set(colon) {
+                Ok(value.unwrap_or_default())
+            } else {
+                word_of(shell, word)
+            }
+        }
+        ParamOp::Assign(colon, word) => {
+            if is_set(colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            if !is_assignable(name) {
+                return Err(format!("`{}`: cannot assign in this way.", name));
+            }
+            let word = word_of(shell, word)?;
+            shell.assign_variable(name.to_string(), word.clone());
+            Ok(word)
+        }
+        ParamOp::Error(colon, word) => {
+            if is_set(colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            let message = match word_of(shell, word)? {
+                ref m if m.is_empty() => format!("`{}`: parameter null or not set.", name),
+                m => format!("`{}`: {}", name, m),
+            };
+            // スクリプトの実行はここで終了する
+            if !shell.interactive {
+                eprintln!("error: {}", message);
+                std::process::exit(1);
+            }
+            Err(message)
+        }
+        ParamOp::Alternate(colon, word) => {
+            if is_set(colon) {
+                word_of(shell, word)
+            } else {
+                Ok(String::new())
+            }
+        }
+        ParamOp::RemovePrefix(longest, p) => {
+            let value = value.unwrap_or_default();
+            let p = pattern_of(shell, p)?;
+            Ok(remove_prefix(&value, &p, longest).to_string())
+        }
+        ParamOp::RemoveSuffix(longest, p) => {
+            let value = value.unwrap_or_default();
+            let p = pattern_of(shell, p)?;
+            Ok(remove_suffix(&value, &p, longest).to_string())
+        }
+        ParamOp::Substring(offset, length) => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let len = chars.len() as i64;
+            // 負の値は末尾から数える
+            let offset = number_of(shell, offset)?;
+            let start = if offset < 0 {
+                (len + offset).max(0)
+            } else {
+                offset.min(len)
+            };
+            let end = match length {
+                None => len,
+                Some(length) => {
+                    let length = number_of(shell, length)?;
+                    if length < 0 {
+                        len.saturating_add(length)
+                    } else {
+                        start.saturating_add(length)
+                    }
+                }
+            };
+            // 範囲が値からはみ出さないようにする
+            let start = start.max(0).min(len) as usize;
+            let end = end.max(start as i64).min(len) as usize;
+            Ok(chars[start..end].iter().collect())
+        }
+        ParamOp::Replace(mode, p, string) => {
+            let value = value.unwrap_or_default();
+            let p = pattern_of(shell, p)?;
+            let string = word_of(shell, string)?;
+            Ok(replace(&value, &p, &string, mode))
+        }
+        ParamOp::Upper(all) => Ok(convert_case(&value.unwrap_or_default(), all, true)),
+        ParamOp::Lower(all) => Ok(convert_case(&value.unwrap_or_default(), all, false)),
+    }
+}
+
+// `${name[index]}` の展開 (index が `@` か `*` なら全ての要素を空白で区切って並べる)
+pub fn element(shell: &mut Shell, name: &str, index: Vec<Str>) -> Result<String, String> {
+    let index = word_of(shell, index)?;
+    let values = elements(shell, name);
+    match index.trim() {
+        "@" => Ok(values.join(" ")),
+        "*" => {
+            let sep = super::word::ifs(shell).chars().next().map(String::from);
+            Ok(values.join(&sep.unwrap_or_default()))
+        }
+        index => {
+            let i = super::arithmetic::eval(shell, index)?;
+            // 負の値は末尾から数える
+            let i = if i < 0 { values.len() as i64 + i } else { i };
+            Ok(values.get(i as usize).cloned().unwrap_or_default())
+        }
+    }
+}
+
+// 配列の全ての要素 (配列でない変数は要素が1つの配列とみなす)
+pub fn elements(shell: &Shell, name: &str) -> Vec<String> {
+    match shell.get_array(name) {
+        Some(values) => values,
+        None => lookup(shell, name).into_iter().collect(),
+    }
+}
+
+// 変数の値 (未設定なら None)
+// `$1` などは引数の数より大きければ未設定
+fn lookup(shell: &Shell, name: &str) -> Option<String> {
+    if let Ok(n) = name.parse::<usize>() {
+        if n > shell.positional.len() {
+            return None;
+        }
+    }
+    if let Some(value) = shell.special_variable(name) {
+        return Some(value);
+    }
+    std::env::var(name)
+        .ok()
+        .or_else(|| shell.get_variable(name))
+}
+
+fn is_assignable(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+fn word_of(shell: &mut Shell, parts: Vec<Str>) -> Result<String, String> {
+    let mut word = String::new();
+    for part in parts {
+        word.push_str(&part.extract(shell)?);
+    }
+    Ok(word)
+}
+
+// クォートされた部分はパターンの特別な文字として扱わない
+fn pattern_of(shell: &mut Shell, parts: Vec<Str>) -> Result<String, String> {
+    let mut p = String::new();
+    for part in parts {
+        p.push_str(&part.extract_pattern(shell)?);
+    }
+    Ok(p)
+}
+
+fn number_of(shell: &mut Shell, parts: Vec<Str>) -> Result<i64, String> {
+    let word = word_of(shell, parts)?;
+    word.trim()
+        .parse()
+        .map_err(|_| format!("`{}`: bad substitution.", word))
+}
+
+// 文字の境界の位置 (0 と末尾を含む)
+fn boundaries(s: &str) -> Vec<usize> {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain(Some(s.len()))
+        .collect()
+}
+
+fn remove_prefix<'a>(value: &'a str, p: &str, longest: bool) -> &'a str {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends
+        .into_iter()
+        .find(|&end| pattern::matches(p, &value[..end]))
+    {
+        Some(end) => &value[end..],
+        None => value,
+    }
+}
+
+fn remove_suffix<'a>(value: &'a str, p: &str, longest: bool) -> &'a str {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts
+        .into_iter()
+        .find(|&start| pattern::matches(p, &value[start..]))
+    {
+        Some(start) => &value[..start],
+        None => value,
+    }
+}
+
+// 一致する部分を string に置き換える (それぞれの位置で最長の部分に一致させる)
+fn replace(value: &str, p: &str, string: &str, mode: ReplaceMode) -> String {
+    if p.is_empty() {
+        return value.to_string();
+    }
+    let bounds = boundaries(value);
+    match mode {
+        ReplaceMode::Prefix => {
+            match bounds
+                .iter()
+                .rev()
+                .find(|&&end| pattern::matches(p, &value[..end]))
+            {
+                Some(&end) => format!("{}{}", string, &value[end..]),
+                None => value.to_string(),
+            }
+        }
+        ReplaceMode::Suffix => {
+            match bounds
+                .iter()
+                .find(|&&start| pattern::matches(p, &value[start..]))
+            {
+                Some(&start) => format!("{}{}", &value[..start], string),
+                None => value.to_string(),
+            }
+        }
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut out = String::new();
+            let mut i = 0;
+            let mut replaced = false;
+            while i < bounds.len() {
+                let start = bounds[i];
+                let matched = if replaced && mode == ReplaceMode::First {
+                    None
+                } else {
+                    // 空文字列に一致しても置き換えない
+                    bounds[i + 1..]
+                        .iter()
+                        .rposition(|&end| pattern::matches(p, &value[start..end]))
+                        .map(|j| i + 1 + j)
+                };
+                match matched {
+                    Some(j) => {
+                        out.push_str(string);
+                        replaced = true;
+                        i = j;
+                    }
+                    None => {
+                        if let Some(&next) = bounds.get(i + 1) {
+                            out.push_str(&value[start..next]);
+                        }
+                        i += 1;
+                    }
+                }
+            }
+            out
+        }
+    }
+}
+
+fn convert_case(value: &str, all: bool, upper: bool) -> String {
+    let convert = |s: &str| {
+        if upper {
+            s.to_uppercase()
+        } else {
+            s.to_lowercase()
+        }
+    };
+    if all {
+        return convert(value);
+    }
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) => convert(&first.to_string()) + chars.as_str(),
+        None => String::new(),
+    }
+}
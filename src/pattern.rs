@@ -0,0 +1,238 @@
+// シェルのパターン (`*`, `?`, `[...]`) による文字列のマッチングとパス名展開
+// `\` の直後の文字は特別な意味を持たない
+
+use std::fs;
+use std::path::Path;
+
+// パターンが文字列全体に一致するか
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut pi, mut ti) = (0, 0);
+    // 最後に現れた `*` の位置と、それに対応させ始めた文字列の位置
+    let mut star: Option<(usize, usize)> = None;
+    while ti < text.len() {
+        if let Some(next) = match_one(&pattern, pi, text[ti]) {
+            pi = next;
+            ti += 1;
+            continue;
+        }
+        if pi < pattern.len() && pattern[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+            continue;
+        }
+        // 直前の `*` に1文字多く対応させてやり直す
+        match star {
+            Some((sp, st)) => {
+                pi = sp + 1;
+                ti = st + 1;
+                star = Some((sp, st + 1));
+            }
+            None => return false,
+        }
+    }
+    while pi < pattern.len() && pattern[pi] == '*' {
+        pi += 1;
+    }
+    pi == pattern.len()
+}
+
+// パターンの pi 番目の要素が1文字 c に一致すれば次の要素の位置を返す
+fn match_one(pattern: &[char], pi: usize, c: char) -> Option<usize> {
+    match pattern.get(pi)? {
+        '*' => None,
+        '?' => Some(pi + 1),
+        '[' => match match_class(pattern, pi, c) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            // 閉じていない `[` は普通の文字として扱う
+            None if c == '[' => Some(pi + 1),
+            None => None,
+        },
+        '\\' if pi + 1 < pattern.len() => {
+            if pattern[pi + 1] == c {
+                Some(pi + 2)
+            } else {
+                None
+            }
+        }
+        &p if p == c => Some(pi + 1),
+        _ => None,
+    }
+}
+
+// `[...]` (先頭が `!` か `^` なら否定) に c が含まれるかと、`]` の次の位置を返す
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = match pattern.get(i) {
+        Some('!') | Some('^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut lo = *pattern.get(i)?;
+        if lo == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if lo == '\\' {
+            i += 1;
+            lo = *pattern.get(i)?;
+        }
+        i += 1;
+
+        // 範囲指定 (a-z)
+        if pattern.get(i) == Some(&'-') && !matches!(pattern.get(i + 1), None | Some(']')) {
+            let mut hi = pattern[i + 1];
+            i += 2;
+            if hi == '\\' {
+                hi = *pattern.get(i)?;
+                i += 1;
+            }
+            if lo <= c && c <= hi {
+                matched = true;
+            }
+        } else if lo == c {
+            matched = true;
+        }
+    }
+}
+
+// パターンとして特別な意味を持つ文字を含むか
+// 閉じていない `[` は普通の文字として扱う (`[ -f file ]` など)
+pub fn has_meta(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if match_class(&chars, i, '\0').is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+// `\` によるエスケープを取り除く
+pub fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+// パス名展開
+// パターンに一致するパスを辞書順に並べて返す
+// `**` は0個以上のディレクトリに一致する (隠しディレクトリには入らない)
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (prefix, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => ("", pattern),
+    };
+    let components: Vec<&str> = rest.split('/').filter(|c| !c.is_empty()).collect();
+    let mut results = Vec::new();
+    glob_in(prefix, &components, &mut results);
+
+    // `*/` のように `/` で終わるパターンはディレクトリにだけ一致する
+    if pattern.ends_with('/') {
+        results = results
+            .into_iter()
+            .filter(|p| Path::new(p).is_dir())
+            .map(|p| p + "/")
+            .collect();
+    }
+    results.sort();
+    results
+}
+
+// prefix (空でなければ `/` で終わる) 以下で components に一致するものを集める
+fn glob_in(prefix: &str, components: &[&str], results: &mut Vec<String>) {
+    let (first, rest) = match components.split_first() {
+        Some(c) => c,
+        None => return,
+    };
+    let dir = if prefix.is_empty() { "." } else { prefix };
+
+    if *first == "**" {
+        // ディレクトリを挟まない場合
+        if rest.is_empty() {
+            glob_in(prefix, &["*"], results);
+        } else {
+            glob_in(prefix, rest, results);
+        }
+        for (name, is_dir) in dir_entries(dir) {
+            if is_dir && !name.starts_with('.') {
+                glob_in(&format!("{}{}/", prefix, name), components, results);
+            }
+        }
+        return;
+    }
+
+    if !has_meta(first) {
+        let path = format!("{}{}", prefix, unescape(first));
+        if rest.is_empty() {
+            if fs::symlink_metadata(&path).is_ok() {
+                results.push(path);
+            }
+        } else if Path::new(&path).is_dir() {
+            glob_in(&format!("{}/", path), rest, results);
+        }
+        return;
+    }
+
+    for (name, _) in dir_entries(dir) {
+        // `.` で始まる名前はパターンも `.` で始まる場合だけ一致する
+        if name.starts_with('.') && !first.starts_with('.') {
+            continue;
+        }
+        if !matches(first, &name) {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        if rest.is_empty() {
+            results.push(path);
+        } else if Path::new(&path).is_dir() {
+            glob_in(&format!("{}/", path), rest, results);
+        }
+    }
+}
+
+// ディレクトリの中身の (名前, シンボリックリンクでないディレクトリか) の一覧
+fn dir_entries(dir: &str) -> Vec<(String, bool)> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                e.file_name().into_string().ok().map(|name| (name, is_dir))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// 文字列がそのままの文字として一致するようにエスケープする
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if let '*' | '?' | '[' | ']' | '\\' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,474 @@
+// `printf` の書式
+// `%[flags][width][.precision]conversion` の形の変換と `\n` などのエスケープを展開する
+// 引数が書式の変換より多ければ、書式を先頭から繰り返し使う
+
+// 書式を展開した結果
+pub struct Formatted {
+    pub text: Vec<u8>,       // `\xHH` などで UTF-8 でないバイト列になることもある
+    pub errors: Vec<String>, // 数として読めなかった引数など (出力は続ける)
+}
+
+pub fn format(fmt: &str, args: &[String]) -> Result<Formatted, String> {
+    let mut out = Formatted {
+        text: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut args = Args { args, pos: 0 };
+    loop {
+        let start = args.pos;
+        if let Flow::Stop = format_once(fmt, &mut args, &mut out)? {
+            break;
+        }
+        // 引数を使わない書式は1回だけ
+        if args.pos >= args.args.len() || args.pos == start {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+struct Args<'a> {
+    args: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Args<'a> {
+    // 引数が足りなければ空文字列
+    fn next(&mut self) -> &'a str {
+        let arg = self.args.get(self.pos).map(String::as_str).unwrap_or("");
+        self.pos += 1;
+        arg
+    }
+}
+
+enum Flow {
+    Continue,
+    Stop, // `%b` の引数に `\c` があった
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,  // `-`: 左に寄せる
+    plus: bool,  // `+`: 正の数にも符号を付ける
+    space: bool, // ` `: 正の数の前に空白を付ける
+    zero: bool,  // `0`: 0 で埋める
+    alt: bool,   // `#`: 8進数と16進数に 0 や 0x を付ける
+    width: usize,
+    precision: Option<usize>,
+}
+
+fn format_once(fmt: &str, args: &mut Args, out: &mut Formatted) -> Result<Flow, String> {
+    let chars: Vec<char> = fmt.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                i = unescape(&chars, i + 1, false, &mut out.text);
+                continue;
+            }
+            '%' => {}
+            c => {
+                push_char(&mut out.text, c);
+                i += 1;
+                continue;
+            }
+        }
+        i += 1;
+        let mut spec = Spec::default();
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                '#' => spec.alt = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        // 幅と精度は `*` なら引数から取る
+        let number = |i: &mut usize, args: &mut Args, out: &mut Formatted| -> i64 {
+            let (text, n) = if chars.get(*i) == Some(&'*') {
+                *i += 1;
+                let arg = args.next();
+                (arg.to_string(), parse_int(arg, &mut out.errors))
+            } else {
+                let start = *i;
+                while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+                    *i += 1;
+                }
+                let digits: String = chars[start..*i].iter().collect();
+                let n = if digits.is_empty() {
+                    0
+                } else {
+                    digits.parse().unwrap_or(i64::MAX)
+                };
+                (digits, n)
+            };
+            // C の printf と同じく int に収まらない幅や精度は使わない
+            if n.unsigned_abs() > i32::MAX as u64 {
+                out.errors
+                    .push(format!("`{}`: width or precision too large.", text));
+                return 0;
+            }
+            n
+        };
+        let width = number(&mut i, args, out);
+        if width < 0 {
+            spec.left = true;
+        }
+        spec.width = width.unsigned_abs() as usize;
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            let precision = number(&mut i, args, out);
+            spec.precision = if precision < 0 {
+                None
+            } else {
+                Some(precision as usize)
+            };
+        }
+        let conversion = match chars.get(i) {
+            Some(&c) => c,
+            None => return Err(format!("`{}`: missing conversion character.", fmt)),
+        };
+        i += 1;
+
+        let text = match conversion {
+            '%' => "%".to_string(),
+            's' => truncate(args.next(), spec.precision),
+            'b' => {
+                let arg: Vec<char> = args.next().chars().collect();
+                let mut expanded = Vec::new();
+                let mut j = 0;
+                let mut stop = false;
+                while j < arg.len() {
+                    if arg[j] != '\\' {
+                        push_char(&mut expanded, arg[j]);
+                        j += 1;
+                    } else if arg.get(j + 1) == Some(&'c') {
+                        stop = true;
+                        break;
+                    } else {
+                        j = unescape(&arg, j + 1, true, &mut expanded);
+                    }
+                }
+                // 幅や精度の指定がなければバイト列をそのまま出力する
+                if spec.width == 0 && spec.precision.is_none() {
+                    out.text.extend(expanded);
+                } else {
+                    let expanded = truncate(&String::from_utf8_lossy(&expanded), spec.precision);
+                    spec.zero = false;
+                    out.text.extend(pad(&spec, "", &expanded).bytes());
+                }
+                if stop {
+                    return Ok(Flow::Stop);
+                }
+                continue;
+            }
+            'q' => quote(args.next()),
+            'c' => args.next().chars().take(1).collect(),
+            'd' | 'i' => {
+                let n = parse_int(args.next(), &mut out.errors);
+                let digits = with_precision(n.unsigned_abs().to_string(), spec.precision);
+                // 整数は精度を指定すると 0 で埋めない
+                spec.zero &= spec.precision.is_none();
+                out.text
+                    .extend(pad(&spec, sign(&spec, n < 0), &digits).bytes());
+                continue;
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                // 負の数は2の補数として扱う
+                let n = parse_int(args.next(), &mut out.errors) as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (n.to_string(), ""),
+                    'o' => (
+                        format!("{:o}", n),
+                        if spec.alt && n != 0 { "0" } else { "" },
+                    ),
+                    'x' => (
+                        format!("{:x}", n),
+                        if spec.alt && n != 0 { "0x" } else { "" },
+                    ),
+                    _ => (
+                        format!("{:X}", n),
+                        if spec.alt && n != 0 { "0X" } else { "" },
+                    ),
+                };
+                let digits = with_precision(digits, spec.precision);
+                spec.zero &= spec.precision.is_none();
+                out.text.extend(pad(&spec, prefix, &digits).bytes());
+                continue;
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let x = parse_float(args.next(), &mut out.errors);
+                let digits = format_float(x.abs(), conversion, &spec);
+                // inf と nan は 0 で埋めない
+                if !x.is_finite() {
+                    spec.zero = false;
+                }
+                out.text
+                    .extend(pad(&spec, sign(&spec, x.is_sign_negative()), &digits).bytes());
+                continue;
+            }
+            c => return Err(format!("`%{}`: invalid conversion.", c)),
+        };
+        // 文字列は 0 で埋めない
+        spec.zero = false;
+        out.text.extend(pad(&spec, "", &text).bytes());
+    }
+    Ok(Flow::Continue)
+}
+
+// `\` の後の i 文字目からエスケープを展開し、続きの位置を返す
+// `%b` の引数では8進数を `\0nnn` と書く
+fn unescape(chars: &[char], mut i: usize, b: bool, out: &mut Vec<u8>) -> usize {
+    let c = match chars.get(i) {
+        Some(&c) => c,
+        None => {
+            out.push(b'\\');
+            return i;
+        }
+    };
+    i += 1;
+    // max 桁までの数を読む
+    let number = |i: &mut usize, radix: u32, max: usize| {
+        let start = *i;
+        while *i < chars.len() && *i - start < max && chars[*i].is_digit(radix) {
+            *i += 1;
+        }
+        let digits: String = chars[start..*i].iter().collect();
+        u32::from_str_radix(&digits, radix).ok()
+    };
+    let escaped = match c {
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' | 'E' => '\x1b',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        '\\' => '\\',
+        '"' => '"',
+        '\'' => '\'',
+        // 8進数と16進数はそのバイトを出力する
+        '0' if b => {
+            out.push(number(&mut i, 8, 3).unwrap_or(0) as u8);
+            return i;
+        }
+        '0'..='7' if !b => {
+            i -= 1;
+            out.push(number(&mut i, 8, 3).unwrap_or(0) as u8);
+            return i;
+        }
+        'x' => match number(&mut i, 16, 2) {
+            Some(n) => {
+                out.push(n as u8);
+                return i;
+            }
+            None => {
+                out.extend(b"\\x");
+                return i;
+            }
+        },
+        'u' | 'U' => {
+            let max = if c == 'u' { 4 } else { 8 };
+            match number(&mut i, 16, max).and_then(std::char::from_u32) {
+                Some(c) => c,
+                None => {
+                    out.push(b'\\');
+                    push_char(out, c);
+                    return i;
+                }
+            }
+        }
+        c => {
+            // 知らないエスケープはそのまま
+            out.push(b'\\');
+            c
+        }
+    };
+    push_char(out, escaped);
+    i
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    out.extend(c.encode_utf8(&mut buf).bytes());
+}
+
+// 幅に合わせて空白 (または数の場合は符号などの後に 0) で埋める
+fn pad(spec: &Spec, prefix: &str, body: &str) -> String {
+    let len = prefix.chars().count() + body.chars().count();
+    let fill = spec.width.saturating_sub(len);
+    if spec.left {
+        format!("{}{}{}", prefix, body, " ".repeat(fill))
+    } else if spec.zero {
+        format!("{}{}{}", prefix, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), prefix, body)
+    }
+}
+
+fn sign(spec: &Spec, negative: bool) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn truncate(s: &str, precision: Option<usize>) -> String {
+    match precision {
+        Some(n) => s.chars().take(n).collect(),
+        None => s.to_string(),
+    }
+}
+
+// 整数の精度は最低限の桁数
+fn with_precision(digits: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(n) if n > digits.len() => format!("{}{}", "0".repeat(n - digits.len()), digits),
+        _ => digits,
+    }
+}
+
+fn format_float(x: f64, conversion: char, spec: &Spec) -> String {
+    let upper = conversion.is_ascii_uppercase();
+    let text = if x.is_nan() {
+        "nan".to_string()
+    } else if x.is_infinite() {
+        "inf".to_string()
+    } else {
+        let precision = spec.precision.unwrap_or(6);
+        match conversion {
+            'f' | 'F' => format!("{:.*}", precision, x),
+            'e' | 'E' => exponent(x, precision),
+            _ => {
+                // 指数が -4 以上で精度より小さければ `%f`、それ以外は `%e` の形にする
+                let precision = precision.max(1);
+                let exp = exponent(x, precision - 1);
+                let e: i32 = exp[exp.find('e').unwrap() + 1..].parse().unwrap();
+                let text = if e < -4 || e >= precision as i32 {
+                    exp
+                } else {
+                    format!("{:.*}", (precision as i32 - 1 - e) as usize, x)
+                };
+                if spec.alt {
+                    text
+                } else {
+                    strip_zeros(&text)
+                }
+            }
+        }
+    };
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+// `1.500000e+00` の形 (指数は2桁以上)
+fn exponent(x: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = text.split_at(text.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exp.abs())
+}
+
+// 小数部の末尾の 0 を取り除く (`%g`)
+fn strip_zeros(text: &str) -> String {
+    let (number, exp) = match text.find('e') {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    if !number.contains('.') {
+        return text.to_string();
+    }
+    let number = number.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", number, exp)
+}
+
+// 数の引数 (`0x1f` や `017` も読める、`'a` は文字コード)
+// 読めなければエラーを記録して 0 にする
+fn parse_int(arg: &str, errors: &mut Vec<String>) -> i64 {
+    let s = arg.trim();
+    if s.is_empty() {
+        return 0;
+    }
+    if let Some(c) = char_code(s) {
+        return c as i64;
+    }
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    match parsed {
+        Ok(n) if negative => n.wrapping_neg(),
+        Ok(n) => n,
+        Err(_) => {
+            errors.push(format!("`{}`: invalid number.", arg));
+            0
+        }
+    }
+}
+
+fn parse_float(arg: &str, errors: &mut Vec<String>) -> f64 {
+    let s = arg.trim();
+    if s.is_empty() {
+        return 0.0;
+    }
+    if let Some(c) = char_code(s) {
+        return c as u32 as f64;
+    }
+    match s.parse() {
+        Ok(x) => x,
+        // 0x1f のような整数も受け付ける
+        Err(_) => {
+            let mut int_errors = Vec::new();
+            let n = parse_int(s, &mut int_errors);
+            if !int_errors.is_empty() {
+                errors.push(format!("`{}`: invalid number.", arg));
+            }
+            n as f64
+        }
+    }
+}
+
+// `'a` や `"a` の形なら a の文字コード
+fn char_code(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some('\'') | Some('"') => chars.next(),
+        _ => None,
+    }
+}
+
+// シェルの入力として再び使える形にクォートする (`%q`)
+fn quote(s: &str) -> String {
+    if s.is_empty() {
+        return "''".to_string();
+    }
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_./:=@%+,-".contains(c);
+    if s.chars().all(safe) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
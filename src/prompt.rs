@@ -0,0 +1,199 @@
+// $PS1, $PS2 からプロンプトの文字列を作る
+// `\u` などのエスケープを置き換えたあと、変数とコマンド置換 `$(...)` を展開する
+
+use super::line_editor::{IGNORE_END, IGNORE_START};
+use super::msh_grammar;
+use super::structures::*;
+use super::terminfo::Terminfo;
+
+use nix::unistd;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PS1: &str =
+    "\\c{yellow}\\c{bold}\\u\\c{reset}@\\c{cyan}\\c{bold}\\w\\c{reset} \\c{bold}\\$\\c{reset} ";
+pub const DEFAULT_PS2: &str = "> ";
+
+const COLORS: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// 変数 name (PS1 や PS2) からプロンプトを作る
+pub fn expand_variable(shell: &mut Shell, name: &str, default: &str) -> String {
+    let template = env::var(name)
+        .ok()
+        .or_else(|| shell.get_variable(name))
+        .unwrap_or_else(|| default.to_string());
+    expand(shell, &template)
+}
+
+pub fn expand(shell: &mut Shell, template: &str) -> String {
+    let terminfo = Terminfo::from_env();
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        // 置き換えた文字列はこのあとの展開の対象にしない
+        let replaced = match chars.next() {
+            Some('u') => user_name(),
+            Some('h') => host_name().split('.').next().unwrap_or("").to_string(),
+            Some('H') => host_name(),
+            Some('w') => current_dir(false),
+            Some('W') => current_dir(true),
+            Some('?') => shell.last_status.to_string(),
+            Some('j') => shell.jobs.len().to_string(),
+            Some('t') => time(|h, m, s| format!("{:02}:{:02}:{:02}", h, m, s)),
+            Some('T') => time(|h, m, s| format!("{:02}:{:02}:{:02}", (h + 11) % 12 + 1, m, s)),
+            Some('A') => time(|h, m, _| format!("{:02}:{:02}", h, m)),
+            Some('g') => git_branch().unwrap_or_default(),
+            Some('$') => prompt_char().to_string(),
+            Some('n') => "\n".to_string(),
+            Some('e') => "\x1b".to_string(),
+            Some('a') => "\x07".to_string(),
+            Some('[') => IGNORE_START.to_string(),
+            Some(']') => IGNORE_END.to_string(),
+            Some('c') if chars.peek() == Some(&'{') => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                match terminfo.as_ref().and_then(|t| attribute(t, &name)) {
+                    Some(seq) => format!("{}{}{}", IGNORE_START, seq, IGNORE_END),
+                    None => String::new(),
+                }
+            }
+            Some('\\') => "\\".to_string(),
+            Some(c) => format!("\\{}", c),
+            None => "\\".to_string(),
+        };
+        out.push_str(&escape(&replaced));
+    }
+
+    match msh_grammar::heredoc_body(&out, shell.options.posix) {
+        Ok(parts) => Str::Quoted(parts).extract(shell).unwrap_or(out),
+        Err(_) => out,
+    }
+}
+
+// `\c{...}` で指定する色や文字の装飾
+// 色は名前か番号で指定し、端末が対応していなければ何も出力しない
+fn attribute(terminfo: &Terminfo, name: &str) -> Option<String> {
+    match name {
+        "bold" => terminfo.bold(),
+        "underline" => terminfo.underline(),
+        "reverse" => terminfo.reverse(),
+        "reset" => terminfo.reset(),
+        name => {
+            let color = match COLORS.iter().position(|&c| c == name) {
+                Some(i) => i as i32,
+                None => name.parse().ok()?,
+            };
+            terminfo.foreground(color)
+        }
+    }
+}
+
+// 後で変数などを展開するときに文字どおりに扱われるようにする
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('$', "\\$")
+}
+
+fn user_name() -> String {
+    if let Ok(user) = env::var("USER") {
+        return user;
+    }
+    let passwd = unsafe { libc::getpwuid(libc::getuid()) };
+    if passwd.is_null() {
+        return "(unknown)".to_string();
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr((*passwd).pw_name) };
+    name.to_string_lossy().into_owned()
+}
+
+fn prompt_char() -> char {
+    if unistd::geteuid().is_root() {
+        '#'
+    } else {
+        '$'
+    }
+}
+
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    match unistd::gethostname(&mut buf) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+// カレントディレクトリ (ホームディレクトリ以下は `~` で表す)
+// basename_only なら最後の要素だけ
+fn current_dir(basename_only: bool) -> String {
+    let cwd = match env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(_) => return String::new(),
+    };
+    let home = env::var("HOME")
+        .ok()
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from);
+    if home.as_ref() == Some(&cwd) {
+        return "~".to_string();
+    }
+    if basename_only {
+        return match cwd.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => cwd.display().to_string(),
+        };
+    }
+    match home.and_then(|h| cwd.strip_prefix(h).ok().map(Path::to_path_buf)) {
+        Some(rest) => format!("~/{}", rest.display()),
+        None => cwd.display().to_string(),
+    }
+}
+
+fn time<F: Fn(i32, i32, i32) -> String>(format: F) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        libc::localtime_r(&now, &mut tm);
+    }
+    format(tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+// カレントディレクトリを含む git リポジトリのブランチ名
+// HEAD がブランチを指していなければコミットの短いハッシュを返す
+fn git_branch() -> Option<String> {
+    let mut dir = env::current_dir().ok()?;
+    let git_dir = loop {
+        let candidate = dir.join(".git");
+        if candidate.is_dir() {
+            break candidate;
+        }
+        if candidate.is_file() {
+            // worktree やサブモジュールでは `.git` がファイルで、実際の場所が書いてある
+            let content = fs::read_to_string(&candidate).ok()?;
+            let path = content.trim().strip_prefix("gitdir:")?.trim();
+            break dir.join(path);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    };
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string(),
+            )
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
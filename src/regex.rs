@@ -0,0 +1,90 @@
+// `[[ a =~ regex ]]` の正規表現 (POSIX の拡張正規表現、libc の regcomp を使う)
+
+use std::ffi::CString;
+
+// text の中で pattern に一致する部分を探す
+// 一致すれば一致した部分全体と、各括弧に一致した部分 (一致しなかった括弧は空) を返す
+pub fn search(pattern: &str, text: &str) -> Result<Option<Vec<String>>, String> {
+    let c_pattern = CString::new(pattern).map_err(|_| format!("`{}`: invalid regex.", pattern))?;
+    let c_text = CString::new(text).map_err(|_| format!("`{}`: invalid string.", text))?;
+    let mut regex: libc::regex_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED) } != 0 {
+        return Err(format!("`{}`: invalid regex.", pattern));
+    }
+    let mut matches = vec![
+        libc::regmatch_t {
+            rm_so: -1,
+            rm_eo: -1
+        };
+        1 + count_groups(pattern)
+    ];
+    let result = unsafe {
+        libc::regexec(
+            &regex,
+            c_text.as_ptr(),
+            matches.len(),
+            matches.as_mut_ptr(),
+            0,
+        )
+    };
+    unsafe { libc::regfree(&mut regex) };
+    match result {
+        0 => Ok(Some(
+            matches
+                .iter()
+                .map(|m| {
+                    if m.rm_so < 0 {
+                        String::new()
+                    } else {
+                        // 位置はバイト単位なので、文字の途中で切れても落ちないようにする
+                        let bytes = &text.as_bytes()[m.rm_so as usize..m.rm_eo as usize];
+                        String::from_utf8_lossy(bytes).into_owned()
+                    }
+                })
+                .collect(),
+        )),
+        libc::REG_NOMATCH => Ok(None),
+        _ => Err(format!("`{}`: regex match failed.", pattern)),
+    }
+}
+
+// 正規表現に含まれる括弧の数 (`\(` や `[(]` は数えない)
+fn count_groups(pattern: &str) -> usize {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut count = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '(' => count += 1,
+            '[' => {
+                // `[]...]` と `[^]...]` の最初の `]` は文字の一つ
+                i += 1;
+                if chars.get(i) == Some(&'^') {
+                    i += 1;
+                }
+                if chars.get(i) == Some(&']') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i] != ']' {
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    count
+}
+
+// 正規表現の中で普通の文字として扱われるようにする
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
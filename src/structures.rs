@@ -1,7 +1,16 @@
 use super::execute;
 
+use nix::sys::signal;
+use nix::sys::termios;
+use nix::unistd;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::fmt;
+use std::os::unix::io::RawFd;
 use std::path;
 
 #[derive(Clone)]
@@ -9,12 +18,140 @@ pub enum CommandType {
     External(path::PathBuf),
     Builtin(fn(&mut Shell, Vec<String>) -> execute::ExitCode),
     Alias(String),
+    Function(List),
 }
 
 pub struct Shell<'a> {
     pub parent: Option<&'a Shell<'a>>,
-    pub command_table: HashMap<String, CommandType>,
+    pub command_table: HashMap<String, CommandType>, // 組み込みコマンド、エイリアス、関数
+    pub command_hash: RefCell<CommandHash>, // $PATH で見つけたコマンド (一番外側の Shell だけが使う)
     pub variables: HashMap<String, String>,
+    pub arrays: HashMap<String, Vec<String>>, // 配列のシェル変数 (`read -a` などで設定する)
+    pub locals: HashSet<String>,              // 関数の中で local で宣言された変数
+    pub unset_globals: HashSet<String>,       // 関数の中で unset された関数の外の変数
+    pub jobs: Vec<Job>,
+    pub job_control: Option<JobControl>, // ジョブ制御が有効な場合のみSome
+    pub interactive: bool,
+    pub pid: unistd::Pid,                 // $$ (サブシェルでも元のシェルのもの)
+    pub name: String,                     // $0
+    pub positional: Vec<String>,          // $1, $2, ...
+    pub last_status: execute::ExitCode,   // $?
+    pub last_bg_pid: Option<unistd::Pid>, // $!
+    pub loop_depth: usize,                // 実行中のループの深さ
+    pub flow: Option<Flow>,               // break/continue/return が実行された
+    pub in_function: bool,                // 関数の中で実行しているか
+    pub options: Options,                 // `set -o` で切り替えるオプション
+    pub completions: HashMap<String, CompletionSpec>, // `complete` で登録された引数の補完方法
+}
+
+// $PATH で見つけたコマンドのパスの記憶 (`hash` で確認・消去できる)
+#[derive(Debug, Clone, Default)]
+pub struct CommandHash {
+    pub path: String, // 調べたときの $PATH (変わったら全て忘れる)
+    pub entries: BTreeMap<String, path::PathBuf>,
+}
+
+// コマンドの引数の補完候補の作り方
+#[derive(Debug, Clone)]
+pub enum CompletionSpec {
+    Words(Vec<String>), // complete -W "word..."
+    Function(String),   // complete -F function (出力の各行が候補)
+    Command(String),    // complete -C command (出力の各行が候補)
+}
+
+// パス名展開で一致するものが無かったときの扱い
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlobNoMatch {
+    Literal, // パターンをそのまま残す
+    Error,   // エラーにしてコマンドを実行しない (failglob)
+    Null,    // 単語を取り除く (nullglob)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub glob_nomatch: GlobNoMatch,
+    pub posix: bool, // POSIX の sh と同じ文法で解析する
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            glob_nomatch: GlobNoMatch::Literal,
+            posix: false,
+        }
+    }
+    // (名前, 有効か) の一覧
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("failglob", self.glob_nomatch == GlobNoMatch::Error),
+            ("nullglob", self.glob_nomatch == GlobNoMatch::Null),
+            ("posix", self.posix),
+        ]
+    }
+    // 名前でオプションを切り替える (知らない名前なら false を返す)
+    pub fn set(&mut self, name: &str, enable: bool) -> bool {
+        let nomatch = match name {
+            "failglob" => GlobNoMatch::Error,
+            "nullglob" => GlobNoMatch::Null,
+            "posix" => {
+                self.posix = enable;
+                return true;
+            }
+            _ => return false,
+        };
+        if enable {
+            self.glob_nomatch = nomatch;
+        } else if self.glob_nomatch == nomatch {
+            self.glob_nomatch = GlobNoMatch::Literal;
+        }
+        true
+    }
+}
+
+// ループや関数の実行を中断する指示 (数値は抜けるループの数)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Break(usize),
+    Continue(usize),
+    Return,
+    Interrupt, // フォアグラウンドのコマンドが SIGINT で終了した
+}
+
+// ジョブ制御のためにシェル自身について覚えておく情報
+pub struct JobControl {
+    pub pgid: unistd::Pid,
+    pub tmodes: termios::Termios,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(execute::ExitCode),
+}
+
+pub struct Job {
+    pub id: usize,
+    pub pgid: unistd::Pid,
+    pub command: String,
+    pub processes: Vec<Process>,
+    pub tmodes: Option<termios::Termios>, // 停止したときの端末の設定
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessState {
+    Running,
+    Stopped,
+    Exited(execute::ExitCode),
+    Signaled(signal::Signal, bool), // コアダンプしたか
+}
+
+// パイプラインの各コマンドに対応するプロセス
+// 組み込みコマンドのようにシェル自身で実行したものは pid が None になる
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: Option<unistd::Pid>,
+    pub state: ProcessState,
 }
 
 #[derive(Debug, Clone)]
@@ -43,36 +180,89 @@ pub enum Pipe {
 #[derive(Debug, Clone)]
 pub enum Executable {
     File {
+        assignments: Vec<(String, Str)>, // コマンドの前に書いた `name=value`
         command_name: Str,
         arguments: Vec<Str>,
     },
+    Assignment(Vec<(String, Str)>), // コマンドのない `name=value`
+    Arithmetic(Vec<Str>),           // `((...))` (結果が 0 以外なら成功)
+    Conditional(CondExpr),          // `[[ ... ]]`
     SubShell(List),
+    Group(List),
+    If {
+        conditions: Vec<(List, List)>, // (条件, 本体) if と elif の並び
+        otherwise: Option<List>,
+    },
+    While {
+        condition: List,
+        body: List,
+        until: bool, // until なら条件が成り立たない間だけ繰り返す
+    },
+    For {
+        variable: String,
+        words: Option<Vec<Str>>, // in が無ければ位置パラメータ
+        body: List,
+    },
+    Case {
+        word: Str,
+        arms: Vec<CaseArm>,
+    },
+    FunctionDef {
+        name: String,
+        body: List,
+    },
+}
+
+// `[[ ... ]]` の条件式
+// 単語は分割もパス名展開もしない
+#[derive(Debug, Clone)]
+pub enum CondExpr {
+    Unary(String, Str),                // `-f file` など
+    Binary(String, Str, Str),          // `a == pattern`, `a -lt b` など
+    Regex(Str, Vec<Str>),              // `a =~ regex` (クォートされた部分は普通の文字)
+    Word(Str),                         // 空文字列でなければ真
+    Not(Box<CondExpr>),                // `! e`
+    And(Box<CondExpr>, Box<CondExpr>), // `a && b`
+    Or(Box<CondExpr>, Box<CondExpr>),  // `a || b`
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<Str>,
+    pub body: Option<List>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Command {
     pub exe: Executable,
-    pub redirect_in: Option<RedirectIn>,
-    pub redirect_out: Option<RedirectOut>,
-    pub redirect_err: Option<RedirectOut>,
+    pub redirects: Vec<Redirect>, // 書かれた順に適用する
 }
 
-pub enum Redirect {
-    In(RedirectIn),
-    Out(RedirectOut),
-    Err(RedirectOut),
+// `n>file` のようなリダイレクト (fd はリダイレクトするファイルディスクリプタの番号)
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub fd: RawFd,
+    pub target: RedirectTarget,
 }
 
 #[derive(Debug, Clone)]
-pub enum RedirectIn {
-    Normal(Str),
-    Heredoc(String),
+pub enum RedirectTarget {
+    Input(Str),       // `<`
+    Heredoc(HereDoc), // `<<`, `<<-`
+    Output(Str),      // `>` (既にあるファイルには書き込まない)
+    Overwrite(Str),   // `>=`
+    Append(Str),      // `>+`
+    ReadWrite(Str),   // `<>`
+    Duplicate(Str),   // `>&n`, `<&n`
+    Close,            // `>&-`, `<&-`
 }
+
 #[derive(Debug, Clone)]
-pub enum RedirectOut {
-    Normal(Str),
-    Overwrite(Str),
-    Append(Str),
+pub struct HereDoc {
+    pub delimiter: String,
+    pub quoted: bool,     // 区切り文字がクォートされていたら本文を展開しない
+    pub strip_tabs: bool, // `<<-` なら各行の先頭のタブを取り除く
+    pub body: Str,
 }
 
 #[derive(Debug, Clone)]
@@ -80,7 +270,453 @@ pub enum Str {
     Raw(String),
     Variable(String),
     SubShellResult(List),
-    Quoted(Vec<Str>),
+    Quoted(Vec<Str>),           // クォートされた部分 (分割もパス名展開もしない)
+    Glob(String),               // パス名展開するパターン
+    Word(Vec<Str>),             // `a"b"$c` のように続けて書かれた部分からなる単語
+    Tilde(String),              // `~user` (`~` だけならユーザー名は空)
+    Brace(Vec<Vec<Str>>),       // `{a,b}` の選択肢
+    Parameter(String, ParamOp), // `${name:-word}` などの操作付きの変数
+    Arithmetic(Vec<Str>),       // `$((...))` (中の変数などを展開してから計算する)
+    Element(String, Vec<Str>),  // `${name[index]}` (index が `@` か `*` なら全ての要素)
+    // `{1..10..2}` の始め、終わり、増分 (並びは単語を展開するときに作る)
+    Sequence(String, String, Option<String>),
+}
+
+// `${name...}` で変数の値に対して行う操作
+// 単語やパターンはクォートや変数を含むことがあるので、部分に分けて持つ
+#[derive(Debug, Clone)]
+pub enum ParamOp {
+    Length, // `${#name}`
+    Count,  // `${#name[@]}` (配列の要素の数)
+    // bool は `:` 付きか (空文字列も未設定とみなす)
+    Default(bool, Vec<Str>),   // `${name:-word}`
+    Assign(bool, Vec<Str>),    // `${name:=word}`
+    Error(bool, Vec<Str>),     // `${name:?word}`
+    Alternate(bool, Vec<Str>), // `${name:+word}`
+    // bool は最長一致か
+    RemovePrefix(bool, Vec<Str>), // `${name#pattern}`, `${name##pattern}`
+    RemoveSuffix(bool, Vec<Str>), // `${name%pattern}`, `${name%%pattern}`
+    Substring(Vec<Str>, Option<Vec<Str>>), // `${name:offset:length}`
+    Replace(ReplaceMode, Vec<Str>, Vec<Str>), // `${name/pattern/string}`
+    // bool は全ての文字を変換するか (false なら先頭の1文字だけ)
+    Upper(bool), // `${name^^}`, `${name^}`
+    Lower(bool), // `${name,,}`, `${name,}`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaceMode {
+    First,  // `/`
+    All,    // `//`
+    Prefix, // `/#`
+    Suffix, // `/%`
+}
+
+impl Str {
+    // 続けて書かれた部分から単語を作る (1つだけならそのまま)
+    pub fn word(mut parts: Vec<Str>) -> Str {
+        if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Str::Word(parts)
+        }
+    }
+}
+
+impl HereDoc {
+    pub fn new(delimiter: String, quoted: bool, strip_tabs: bool) -> HereDoc {
+        HereDoc {
+            delimiter,
+            quoted,
+            strip_tabs,
+            body: Str::Raw(String::new()),
+        }
+    }
+}
+
+impl List {
+    // 改行で区切られた複数のリストを1つにまとめる
+    // バックグラウンドで実行するリストはグループにして、その部分だけがバックグラウンドになるようにする
+    pub fn concat(mut lists: Vec<List>) -> List {
+        if lists.len() == 1 {
+            return lists.pop().unwrap();
+        }
+        let mut connectors = Vec::new();
+        for list in lists {
+            if list.1.is_some() {
+                let cmd = Command {
+                    exe: Executable::Group(list),
+                    redirects: Vec::new(),
+                };
+                let pipeline = PipeLine(VecDeque::from(vec![Pipe::PipeLineTerm(cmd)]));
+                connectors.push(Connector::Continue(pipeline));
+                continue;
+            }
+            for c in list.0 {
+                connectors.push(match c {
+                    Connector::ListTerm(p) => Connector::Continue(p),
+                    c => c,
+                });
+            }
+        }
+        if let Some(Connector::Continue(p)) = connectors.pop() {
+            connectors.push(Connector::ListTerm(p));
+        }
+        List(connectors, None)
+    }
+
+    // 本文を読み込む必要のあるヒアドキュメントを出現順に集める
+    pub fn heredocs_mut(&mut self) -> Vec<&mut HereDoc> {
+        let mut docs = Vec::new();
+        for connector in self.0.iter_mut() {
+            let pipeline = match connector {
+                Connector::Continue(p)
+                | Connector::And(p)
+                | Connector::Or(p)
+                | Connector::ListTerm(p) => p,
+            };
+            for pipe in pipeline.0.iter_mut() {
+                let cmd = match pipe {
+                    Pipe::Stdout(c) | Pipe::Both(c) | Pipe::PipeLineTerm(c) => c,
+                };
+                docs.append(&mut cmd.heredocs_mut());
+            }
+        }
+        docs
+    }
+}
+impl Command {
+    pub fn heredocs_mut(&mut self) -> Vec<&mut HereDoc> {
+        let mut docs = Vec::new();
+        match &mut self.exe {
+            Executable::File { .. }
+            | Executable::Assignment(_)
+            | Executable::Arithmetic(_)
+            | Executable::Conditional(_) => {}
+            Executable::SubShell(list) | Executable::Group(list) => {
+                docs.append(&mut list.heredocs_mut());
+            }
+            Executable::If {
+                conditions,
+                otherwise,
+            } => {
+                for (condition, body) in conditions.iter_mut() {
+                    docs.append(&mut condition.heredocs_mut());
+                    docs.append(&mut body.heredocs_mut());
+                }
+                if let Some(list) = otherwise {
+                    docs.append(&mut list.heredocs_mut());
+                }
+            }
+            Executable::While {
+                condition, body, ..
+            } => {
+                docs.append(&mut condition.heredocs_mut());
+                docs.append(&mut body.heredocs_mut());
+            }
+            Executable::For { body, .. } | Executable::FunctionDef { body, .. } => {
+                docs.append(&mut body.heredocs_mut())
+            }
+            Executable::Case { arms, .. } => {
+                for arm in arms.iter_mut() {
+                    if let Some(list) = &mut arm.body {
+                        docs.append(&mut list.heredocs_mut());
+                    }
+                }
+            }
+        }
+        for redirect in self.redirects.iter_mut() {
+            if let RedirectTarget::Heredoc(doc) = &mut redirect.target {
+                docs.push(doc);
+            }
+        }
+        docs
+    }
+}
+
+// ジョブの表示などに使う、コマンドを文字列に戻す処理
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.iter() {
+            write!(f, "{}", c)?;
+        }
+        if self.1.is_some() {
+            write!(f, " &")?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Connector::Continue(p) => write!(f, "{}; ", p),
+            Connector::And(p) => write!(f, "{} && ", p),
+            Connector::Or(p) => write!(f, "{} || ", p),
+            Connector::ListTerm(p) => write!(f, "{}", p),
+        }
+    }
+}
+impl fmt::Display for PipeLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for p in self.0.iter() {
+            match p {
+                Pipe::Stdout(c) => write!(f, "{} | ", c)?,
+                Pipe::Both(c) => write!(f, "{} |& ", c)?,
+                Pipe::PipeLineTerm(c) => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Executable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Executable::File {
+                assignments,
+                command_name,
+                arguments,
+            } => {
+                for (name, value) in assignments.iter() {
+                    write!(f, "{}={} ", name, value)?;
+                }
+                write!(f, "{}", command_name)?;
+                for a in arguments.iter() {
+                    write!(f, " {}", a)?;
+                }
+            }
+            Executable::Assignment(assignments) => {
+                let assignments: Vec<String> = assignments
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                write!(f, "{}", assignments.join(" "))?;
+            }
+            Executable::Arithmetic(expr) => {
+                write!(f, "((")?;
+                for e in expr.iter() {
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "))")?;
+            }
+            Executable::Conditional(e) => write!(f, "[[ {} ]]", e)?,
+            Executable::SubShell(list) => write!(f, "({})", list)?,
+            Executable::Group(list) => write!(f, "{{ {}; }}", list)?,
+            Executable::If {
+                conditions,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in conditions.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    write!(f, "{} {}; then {}; ", keyword, condition, body)?;
+                }
+                if let Some(list) = otherwise {
+                    write!(f, "else {}; ", list)?;
+                }
+                write!(f, "fi")?;
+            }
+            Executable::While {
+                condition,
+                body,
+                until,
+            } => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{} {}; do {}; done", keyword, condition, body)?;
+            }
+            Executable::For {
+                variable,
+                words,
+                body,
+            } => {
+                write!(f, "for {}", variable)?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for w in words.iter() {
+                        write!(f, " {}", w)?;
+                    }
+                }
+                write!(f, "; do {}; done", body)?;
+            }
+            Executable::Case { word, arms } => {
+                write!(f, "case {} in ", word)?;
+                for arm in arms.iter() {
+                    for (i, p) in arm.patterns.iter().enumerate() {
+                        write!(f, "{}{}", if i == 0 { "" } else { " | " }, p)?;
+                    }
+                    match &arm.body {
+                        Some(list) => write!(f, ") {};; ", list)?,
+                        None => write!(f, ") ;; ")?,
+                    }
+                }
+                write!(f, "esac")?;
+            }
+            Executable::FunctionDef { name, body } => write!(f, "{}() {}", name, body)?,
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.exe)?;
+        for redirect in &self.redirects {
+            write!(f, " {}", redirect)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // 省略できる番号は書かない (標準エラー出力は `>!` などで書く)
+        let (op, default_fd) = match &self.target {
+            RedirectTarget::Input(_) => ("<", 0),
+            RedirectTarget::Heredoc(doc) if doc.strip_tabs => ("<<-", 0),
+            RedirectTarget::Heredoc(_) => ("<<", 0),
+            RedirectTarget::ReadWrite(_) => ("<>", 0),
+            RedirectTarget::Output(_) if self.fd == 2 => (">!", 2),
+            RedirectTarget::Output(_) => (">", 1),
+            RedirectTarget::Overwrite(_) if self.fd == 2 => (">=!", 2),
+            RedirectTarget::Overwrite(_) => (">=", 1),
+            RedirectTarget::Append(_) if self.fd == 2 => (">+!", 2),
+            RedirectTarget::Append(_) => (">+", 1),
+            RedirectTarget::Duplicate(_) | RedirectTarget::Close if self.fd == 0 => ("<&", 0),
+            RedirectTarget::Duplicate(_) | RedirectTarget::Close => (">&", 1),
+        };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        match &self.target {
+            RedirectTarget::Input(s)
+            | RedirectTarget::Output(s)
+            | RedirectTarget::Overwrite(s)
+            | RedirectTarget::Append(s)
+            | RedirectTarget::ReadWrite(s) => write!(f, "{} {}", op, s),
+            RedirectTarget::Heredoc(doc) => write!(f, "{} {}", op, doc.delimiter),
+            RedirectTarget::Duplicate(s) => write!(f, "{}{}", op, s),
+            RedirectTarget::Close => write!(f, "{}-", op),
+        }
+    }
+}
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Str::Raw(s) | Str::Glob(s) => write!(f, "{}", s),
+            Str::Variable(v) => write!(f, "${{{}}}", v),
+            Str::Parameter(name, ParamOp::Length) => write!(f, "${{#{}}}", name),
+            Str::Parameter(name, ParamOp::Count) => write!(f, "${{#{}[@]}}", name),
+            Str::Element(name, index) => {
+                write!(f, "${{{}[", name)?;
+                for p in index.iter() {
+                    write!(f, "{}", p)?;
+                }
+                write!(f, "]}}")
+            }
+            Str::Parameter(name, op) => write!(f, "${{{}{}}}", name, op),
+            Str::SubShellResult(list) => write!(f, "$({})", list),
+            Str::Word(parts) => {
+                for p in parts.iter() {
+                    write!(f, "{}", p)?;
+                }
+                Ok(())
+            }
+            Str::Tilde(user) => write!(f, "~{}", user),
+            Str::Brace(alternatives) => {
+                write!(f, "{{")?;
+                for (i, alt) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    for p in alt.iter() {
+                        write!(f, "{}", p)?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            Str::Sequence(start, end, step) => {
+                write!(f, "{{{}..{}", start, end)?;
+                if let Some(step) = step {
+                    write!(f, "..{}", step)?;
+                }
+                write!(f, "}}")
+            }
+            Str::Arithmetic(expr) => {
+                write!(f, "$((")?;
+                for e in expr.iter() {
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "))")
+            }
+            Str::Quoted(cont) => {
+                write!(f, "\"")?;
+                for c in cont.iter() {
+                    write!(f, "{}", c)?;
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+impl fmt::Display for CondExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CondExpr::Unary(op, w) => write!(f, "{} {}", op, w),
+            CondExpr::Binary(op, a, b) => write!(f, "{} {} {}", a, op, b),
+            CondExpr::Regex(a, regex) => {
+                write!(f, "{} =~ ", a)?;
+                for p in regex.iter() {
+                    write!(f, "{}", p)?;
+                }
+                Ok(())
+            }
+            CondExpr::Word(w) => write!(f, "{}", w),
+            CondExpr::Not(e) => write!(f, "! {}", e),
+            CondExpr::And(a, b) => write!(f, "{} && {}", a, b),
+            CondExpr::Or(a, b) => write!(f, "( {} || {} )", a, b),
+        }
+    }
+}
+
+impl fmt::Display for ParamOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn parts(f: &mut fmt::Formatter, parts: &[Str]) -> fmt::Result {
+            for p in parts {
+                write!(f, "{}", p)?;
+            }
+            Ok(())
+        }
+        let colon = |c: &bool| if *c { ":" } else { "" };
+        let twice = |all: &bool, op: &'static str| if *all { op.repeat(2) } else { op.to_string() };
+        match self {
+            ParamOp::Length | ParamOp::Count => Ok(()),
+            ParamOp::Default(c, w) => write!(f, "{}-", colon(c)).and_then(|_| parts(f, w)),
+            ParamOp::Assign(c, w) => write!(f, "{}=", colon(c)).and_then(|_| parts(f, w)),
+            ParamOp::Error(c, w) => write!(f, "{}?", colon(c)).and_then(|_| parts(f, w)),
+            ParamOp::Alternate(c, w) => write!(f, "{}+", colon(c)).and_then(|_| parts(f, w)),
+            ParamOp::RemovePrefix(l, p) => write!(f, "{}", twice(l, "#")).and_then(|_| parts(f, p)),
+            ParamOp::RemoveSuffix(l, p) => write!(f, "{}", twice(l, "%")).and_then(|_| parts(f, p)),
+            ParamOp::Substring(offset, length) => {
+                write!(f, ":")?;
+                parts(f, offset)?;
+                if let Some(length) = length {
+                    write!(f, ":")?;
+                    parts(f, length)?;
+                }
+                Ok(())
+            }
+            ParamOp::Replace(mode, pattern, string) => {
+                let op = match mode {
+                    ReplaceMode::First => "/",
+                    ReplaceMode::All => "//",
+                    ReplaceMode::Prefix => "/#",
+                    ReplaceMode::Suffix => "/%",
+                };
+                write!(f, "{}", op)?;
+                parts(f, pattern)?;
+                write!(f, "/")?;
+                parts(f, string)
+            }
+            ParamOp::Upper(all) => write!(f, "{}", twice(all, "^")),
+            ParamOp::Lower(all) => write!(f, "{}", twice(all, ",")),
+        }
+    }
 }
 
 // デバッグ用関数
@@ -175,6 +811,7 @@ impl Executable {
             Executable::File {
                 command_name: name,
                 arguments: arg,
+                ..
             } => format!(
                 "{}File {{ name: {:?}, argument: {:?} }}",
                 gen_indent(indent),
@@ -187,20 +824,19 @@ impl Executable {
                 cb.print(indent + INDENT_WIDTH),
                 gen_indent(indent)
             ),
+            compound => format!("{}{}", gen_indent(indent), compound),
         }
     }
 }
 impl Command {
     pub fn print(&self, indent: usize) -> String {
         format!(
-            "{}Command {{\n{}exe:\n{}\n{}redirect_in: {:?}\n{}redirect_out: {:?}\n{}}}",
+            "{}Command {{\n{}exe:\n{}\n{}redirects: {:?}\n{}}}",
             gen_indent(indent),
             gen_indent(indent + INDENT_WIDTH),
             self.exe.print(indent + INDENT_WIDTH * 2),
             gen_indent(indent + INDENT_WIDTH),
-            self.redirect_in,
-            gen_indent(indent + INDENT_WIDTH),
-            self.redirect_out,
+            self.redirects,
             gen_indent(indent)
         )
     }
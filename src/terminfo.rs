@@ -0,0 +1,253 @@
+// terminfo データベース (コンパイル済みの形式) から端末の制御文字列を引く
+// プロンプトの色付けに使い、見つからない場合や色のない端末では何も出力しない
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// term.h の並び順での番号
+const MAX_COLORS: usize = 13;
+const ENTER_BOLD_MODE: usize = 27;
+const ENTER_REVERSE_MODE: usize = 34;
+const ENTER_UNDERLINE_MODE: usize = 36;
+const EXIT_ATTRIBUTE_MODE: usize = 39;
+const SET_A_FOREGROUND: usize = 359;
+
+const MAGIC_LEGACY: i16 = 0o432;
+const MAGIC_32BIT: i16 = 0o1036;
+
+pub struct Terminfo {
+    numbers: Vec<i32>,
+    strings: Vec<Option<Vec<u8>>>,
+}
+
+impl Terminfo {
+    // $TERM の端末の情報を読み込む
+    pub fn from_env() -> Option<Terminfo> {
+        let term = env::var("TERM").ok()?;
+        if term.is_empty() || term.contains('/') {
+            return None;
+        }
+        let first = term.chars().next()?;
+        for dir in search_dirs() {
+            // Linux は先頭の文字、macOS は先頭の文字の16進数をディレクトリ名にしている
+            for sub in &[first.to_string(), format!("{:x}", first as u32)] {
+                if let Ok(data) = fs::read(dir.join(sub).join(&term)) {
+                    return Terminfo::parse(&data);
+                }
+            }
+        }
+        None
+    }
+
+    fn parse(data: &[u8]) -> Option<Terminfo> {
+        let short = |i: usize| -> Option<i16> {
+            Some(i16::from_le_bytes([*data.get(i)?, *data.get(i + 1)?]))
+        };
+        let magic = short(0)?;
+        let number_size = match magic {
+            MAGIC_LEGACY => 2,
+            MAGIC_32BIT => 4,
+            _ => return None,
+        };
+        let names_size = short(2)? as usize;
+        let bools_count = short(4)? as usize;
+        let numbers_count = short(6)? as usize;
+        let strings_count = short(8)? as usize;
+        let table_size = short(10)? as usize;
+
+        let mut pos = 12 + names_size + bools_count;
+        // 数値の部分は偶数の位置から始まる
+        pos += pos % 2;
+
+        let mut numbers = Vec::with_capacity(numbers_count);
+        for i in 0..numbers_count {
+            let p = pos + i * number_size;
+            let n = if number_size == 4 {
+                i32::from_le_bytes([
+                    *data.get(p)?,
+                    *data.get(p + 1)?,
+                    *data.get(p + 2)?,
+                    *data.get(p + 3)?,
+                ])
+            } else {
+                i32::from(short(p)?)
+            };
+            numbers.push(n);
+        }
+        pos += numbers_count * number_size;
+
+        let table_start = pos + strings_count * 2;
+        let table = data.get(table_start..table_start + table_size)?;
+        let mut strings = Vec::with_capacity(strings_count);
+        for i in 0..strings_count {
+            let offset = short(pos + i * 2)?;
+            // 負の値はその機能がないことを表す
+            let s = if offset < 0 {
+                None
+            } else {
+                let rest = table.get(offset as usize..)?;
+                let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+                Some(rest[..end].to_vec())
+            };
+            strings.push(s);
+        }
+        Some(Terminfo { numbers, strings })
+    }
+
+    fn string(&self, index: usize) -> Option<&[u8]> {
+        self.strings.get(index)?.as_deref()
+    }
+
+    pub fn colors(&self) -> i32 {
+        self.numbers.get(MAX_COLORS).cloned().unwrap_or(-1)
+    }
+
+    // 文字色を変える制御文字列 (色のない端末では None)
+    pub fn foreground(&self, color: i32) -> Option<String> {
+        if color < 0 || color >= self.colors() {
+            return None;
+        }
+        tparm(self.string(SET_A_FOREGROUND)?, &[color])
+    }
+
+    pub fn bold(&self) -> Option<String> {
+        tparm(self.string(ENTER_BOLD_MODE)?, &[])
+    }
+
+    pub fn underline(&self) -> Option<String> {
+        tparm(self.string(ENTER_UNDERLINE_MODE)?, &[])
+    }
+
+    pub fn reverse(&self) -> Option<String> {
+        tparm(self.string(ENTER_REVERSE_MODE)?, &[])
+    }
+
+    pub fn reset(&self) -> Option<String> {
+        tparm(self.string(EXIT_ATTRIBUTE_MODE)?, &[])
+    }
+}
+
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Ok(dir) = env::var("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(list) = env::var("TERMINFO_DIRS") {
+        dirs.extend(list.split(':').filter(|d| !d.is_empty()).map(PathBuf::from));
+    }
+    for dir in &[
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+// 引数付きの制御文字列 (`%p1%d` など) を展開する
+// 色の指定で使われる範囲の命令に対応する (引数は整数だけ)
+fn tparm(cap: &[u8], params: &[i32]) -> Option<String> {
+    let mut params: Vec<i32> = params.to_vec();
+    params.resize(9, 0);
+    let mut out: Vec<u8> = Vec::new();
+    let mut stack: Vec<i32> = Vec::new();
+    let mut i = 0;
+    while i < cap.len() {
+        let c = cap[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        let op = *cap.get(i)?;
+        i += 1;
+        match op {
+            b'%' => out.push(b'%'),
+            b'd' | b's' => out.extend(stack.pop()?.to_string().into_bytes()),
+            b'c' => out.push(stack.pop()? as u8),
+            b'p' => {
+                let n = cap.get(i)?.checked_sub(b'1')? as usize;
+                i += 1;
+                stack.push(*params.get(n)?);
+            }
+            b'i' => {
+                params[0] += 1;
+                params[1] += 1;
+            }
+            b'{' => {
+                let end = i + cap[i..].iter().position(|&b| b == b'}')?;
+                let n = String::from_utf8_lossy(&cap[i..end]).parse().ok()?;
+                stack.push(n);
+                i = end + 1;
+            }
+            b'\'' => {
+                stack.push(i32::from(*cap.get(i)?));
+                i += 2;
+            }
+            b'+' | b'-' | b'*' | b'/' | b'm' | b'&' | b'|' | b'^' | b'=' | b'<' | b'>' | b'A'
+            | b'O' => {
+                let b = stack.pop()?;
+                let a = stack.pop()?;
+                let n = match op {
+                    b'+' => a.wrapping_add(b),
+                    b'-' => a.wrapping_sub(b),
+                    b'*' => a.wrapping_mul(b),
+                    b'/' => a.checked_div(b).unwrap_or(0),
+                    b'm' => a.checked_rem(b).unwrap_or(0),
+                    b'&' => a & b,
+                    b'|' => a | b,
+                    b'^' => a ^ b,
+                    b'=' => (a == b) as i32,
+                    b'<' => (a < b) as i32,
+                    b'>' => (a > b) as i32,
+                    b'A' => (a != 0 && b != 0) as i32,
+                    _ => (a != 0 || b != 0) as i32,
+                };
+                stack.push(n);
+            }
+            b'!' | b'~' => {
+                let a = stack.pop()?;
+                stack.push(if op == b'!' { (a == 0) as i32 } else { !a });
+            }
+            b'?' | b';' => {}
+            b't' => {
+                if stack.pop()? == 0 {
+                    // 対応する %e か %; まで読み飛ばす
+                    i = skip_branch(cap, i, true)?;
+                }
+            }
+            b'e' => {
+                // then 節を実行し終えたので %; まで読み飛ばす
+                i = skip_branch(cap, i, false)?;
+            }
+            _ => return None,
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+// 条件分岐の読み飛ばし先を求める (入れ子の %? ... %; は飛ばす)
+fn skip_branch(cap: &[u8], mut i: usize, stop_at_else: bool) -> Option<usize> {
+    let mut depth = 0;
+    while i + 1 < cap.len() {
+        if cap[i] == b'%' {
+            match cap[i + 1] {
+                b'?' => depth += 1,
+                b';' if depth == 0 => return Some(i + 2),
+                b';' => depth -= 1,
+                b'e' if depth == 0 && stop_at_else => return Some(i + 2),
+                _ => {}
+            }
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    Some(cap.len())
+}
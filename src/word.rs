@@ -0,0 +1,376 @@
+// 単語の展開 (ブレース展開、フィールド分割とパス名展開)
+// `{a,b}` は最初に別々の単語にする
+// クォートされていない変数やコマンド置換の結果は $IFS の文字で区切って複数の単語にし、
+// パターンを含む単語は一致するパスに置き換える
+
+use super::pattern;
+use super::structures::*;
+
+const DEFAULT_IFS: &str = " \t\n";
+
+// $IFS の値 (未設定なら空白・タブ・改行)
+pub fn ifs(shell: &Shell) -> String {
+    std::env::var("IFS")
+        .ok()
+        .or_else(|| shell.get_variable("IFS"))
+        .unwrap_or_else(|| DEFAULT_IFS.to_string())
+}
+
+// コマンドの引数として展開する
+pub fn expand(shell: &mut Shell, word: Str) -> Result<Vec<String>, String> {
+    let mut fields = Fields::new(ifs(shell));
+    for parts in brace_expand(word) {
+        for part in parts {
+            fields.push(shell, part)?;
+        }
+        fields.end_field();
+    }
+
+    let mut words = Vec::new();
+    for field in fields.done {
+        if !pattern::has_meta(&field.pattern) {
+            words.push(field.literal);
+            continue;
+        }
+        let matched = pattern::glob(&field.pattern);
+        if !matched.is_empty() {
+            words.extend(matched);
+            continue;
+        }
+        match shell.options.glob_nomatch {
+            GlobNoMatch::Literal => words.push(field.literal),
+            GlobNoMatch::Null => {}
+            GlobNoMatch::Error => return Err(format!("no match: `{}`", field.literal)),
+        }
+    }
+    Ok(words)
+}
+
+// 引数以外 (リダイレクト先など) で1つの文字列に展開する
+// 分割はせず、パス名展開は一致するものが1つの場合だけ行う
+pub fn extract(shell: &mut Shell, parts: Vec<Str>) -> Result<String, String> {
+    let field = match single_field(shell, parts)? {
+        Some(field) => field,
+        None => return Ok(String::new()),
+    };
+    if pattern::has_meta(&field.pattern) {
+        let mut matched = pattern::glob(&field.pattern);
+        if matched.len() == 1 {
+            return Ok(matched.remove(0));
+        }
+    }
+    Ok(field.literal)
+}
+
+// `[[ ... ]]` の中のように、分割もパス名展開もせずに1つの文字列に展開する
+pub fn literal(shell: &mut Shell, word: Str) -> Result<String, String> {
+    let field = single_field(shell, vec![word])?;
+    Ok(field.map(|f| f.literal).unwrap_or_default())
+}
+
+fn single_field(shell: &mut Shell, parts: Vec<Str>) -> Result<Option<Field>, String> {
+    let mut fields = Fields::new(String::new());
+    for part in parts {
+        fields.push(shell, part)?;
+    }
+    Ok(fields.current)
+}
+
+// 展開中の1つの単語
+// パス名展開に使うパターン (そのままの文字として扱う部分はエスケープ済み) と、
+// 展開しなかった場合の文字列を両方持つ
+#[derive(Default)]
+struct Field {
+    pattern: String,
+    literal: String,
+}
+
+struct Fields {
+    ifs: String,
+    done: Vec<Field>,
+    current: Option<Field>, // 何も加えていなければ None (単語にならない)
+}
+
+impl Fields {
+    fn new(ifs: String) -> Fields {
+        Fields {
+            ifs,
+            done: Vec::new(),
+            current: None,
+        }
+    }
+
+    fn push(&mut self, shell: &mut Shell, part: Str) -> Result<(), String> {
+        match part {
+            Str::Raw(s) => self.push_literal(&s),
+            Str::Glob(p) => {
+                let field = self.current();
+                field.pattern.push_str(&p);
+                field.literal.push_str(&pattern::unescape(&p));
+            }
+            Str::Word(parts) => {
+                for part in parts {
+                    self.push(shell, part)?;
+                }
+            }
+            Str::Quoted(parts) => self.push_quoted(shell, parts)?,
+            Str::Tilde(user) => self.push_literal(&tilde(&user)),
+            // ブレース展開をしない場所ではそのままの文字にする
+            Str::Brace(alternatives) => {
+                self.push_literal("{");
+                for (i, alt) in alternatives.into_iter().enumerate() {
+                    if i > 0 {
+                        self.push_literal(",");
+                    }
+                    for part in alt {
+                        self.push(shell, part)?;
+                    }
+                }
+                self.push_literal("}");
+            }
+            sequence @ Str::Sequence(..) => self.push_literal(&sequence.to_string()),
+            expansion => {
+                let value = expansion.extract(shell)?;
+                self.push_split(&value);
+            }
+        }
+        Ok(())
+    }
+
+    fn current(&mut self) -> &mut Field {
+        self.current.get_or_insert_with(Field::default)
+    }
+
+    fn end_field(&mut self) {
+        if let Some(field) = self.current.take() {
+            self.done.push(field);
+        }
+    }
+
+    // そのままの文字として加える (空文字列でも単語になる)
+    fn push_literal(&mut self, s: &str) {
+        let field = self.current();
+        field.pattern.push_str(&pattern::escape(s));
+        field.literal.push_str(s);
+    }
+
+    // クォートされていない展開の結果
+    // 分割したそれぞれの部分はパス名展開の対象になる
+    fn push_expanded(&mut self, s: &str) {
+        let field = self.current();
+        field.pattern.push_str(&s.replace('\\', "\\\\"));
+        field.literal.push_str(s);
+    }
+
+    // $IFS の文字で区切って加える
+    // 空白類の文字の並びは1つの区切りとして扱い、それ以外の文字は1文字ごとに単語を区切る
+    fn push_split(&mut self, value: &str) {
+        if self.ifs.is_empty() {
+            if !value.is_empty() {
+                self.push_expanded(value);
+            }
+            return;
+        }
+        let ifs = self.ifs.clone();
+        let is_space = |c: char| c.is_whitespace() && ifs.contains(c);
+        let chars: Vec<char> = value.chars().collect();
+        let mut segment = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if !ifs.contains(chars[i]) {
+                segment.push(chars[i]);
+                i += 1;
+                continue;
+            }
+            if !segment.is_empty() {
+                self.push_expanded(&segment);
+                segment.clear();
+            }
+            while i < chars.len() && is_space(chars[i]) {
+                i += 1;
+            }
+            if i < chars.len() && ifs.contains(chars[i]) {
+                // 空白以外の区切りの前は空の単語にもなる
+                self.current();
+                i += 1;
+                while i < chars.len() && is_space(chars[i]) {
+                    i += 1;
+                }
+            }
+            self.end_field();
+        }
+        if !segment.is_empty() {
+            self.push_expanded(&segment);
+        }
+    }
+
+    // ダブルクォートの中は分割しないが、"$@" と "${name[@]}" はそれぞれの値を別の単語にする
+    fn push_quoted(&mut self, shell: &mut Shell, parts: Vec<Str>) -> Result<(), String> {
+        if !parts.iter().any(is_list) {
+            let s = Str::Quoted(parts).extract(shell)?;
+            self.push_literal(&s);
+            return Ok(());
+        }
+        for part in parts {
+            if !is_list(&part) {
+                let s = part.extract(shell)?;
+                if !s.is_empty() {
+                    self.push_literal(&s);
+                }
+                continue;
+            }
+            let values = match part {
+                Str::Element(name, _) => super::parameter::elements(shell, &name),
+                _ => shell.positional.clone(),
+            };
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    self.end_field();
+                }
+                self.push_literal(value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// `$@` と `${name[@]}`
+fn is_list(part: &Str) -> bool {
+    match part {
+        Str::Variable(v) => v == "@",
+        Str::Element(_, index) => match index.as_slice() {
+            [Str::Raw(s)] => s == "@",
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// 単語を `{...}` の選択肢ごとの部分の並びに展開する
+fn brace_expand(word: Str) -> Vec<Vec<Str>> {
+    let parts = match word {
+        Str::Word(parts) => parts,
+        part => vec![part],
+    };
+    let mut words: Vec<Vec<Str>> = vec![Vec::new()];
+    for part in parts {
+        let part = match part {
+            Str::Sequence(start, end, step) => Str::Brace(
+                sequence(&start, &end, step.as_deref())
+                    .into_iter()
+                    .flatten()
+                    .map(|s| vec![Str::Raw(s)])
+                    .collect(),
+            ),
+            part => part,
+        };
+        match part {
+            Str::Brace(alternatives) => {
+                let expanded: Vec<Vec<Str>> = alternatives
+                    .into_iter()
+                    .flat_map(|alt| brace_expand(Str::Word(alt)))
+                    .collect();
+                words = words
+                    .into_iter()
+                    .flat_map(|prefix| {
+                        expanded.iter().map(move |alt| {
+                            let mut word = prefix.clone();
+                            word.extend(alt.iter().cloned());
+                            word
+                        })
+                    })
+                    .collect();
+            }
+            part => {
+                for word in words.iter_mut() {
+                    word.push(part.clone());
+                }
+            }
+        }
+    }
+    words
+}
+
+// `{1..10..2}` や `{a..e}` の並び (並びとして正しくなければ None)
+// 数の先頭に 0 があれば同じ桁数になるように 0 で埋める
+// 並びは取り出すときに作るので、正しいかを調べるだけなら長い並びでもすぐに終わる
+pub fn sequence(
+    start: &str,
+    end: &str,
+    step: Option<&str>,
+) -> Option<Box<dyn Iterator<Item = String>>> {
+    let step = match step {
+        Some(step) => step.parse::<i64>().ok()?.checked_abs()?.max(1),
+        None => 1,
+    };
+    let range = move |a: i64, b: i64| {
+        let next = move |&n: &i64| {
+            if a <= b {
+                n.checked_add(step)
+            } else {
+                n.checked_sub(step)
+            }
+        };
+        std::iter::successors(Some(a), next)
+            .take_while(move |&n| (a <= b && n <= b) || (a > b && n >= b))
+    };
+    if let (Ok(a), Ok(b)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| {
+            s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Some(Box::new(range(a, b).map(move |n| {
+            if n < 0 {
+                format!(
+                    "-{:0width$}",
+                    n.unsigned_abs(),
+                    width = width.saturating_sub(1)
+                )
+            } else {
+                format!("{:0width$}", n, width = width)
+            }
+        })));
+    }
+    let (a, b) = (start.chars().next()?, end.chars().next()?);
+    if !a.is_ascii_alphabetic() || !b.is_ascii_alphabetic() {
+        return None;
+    }
+    Some(Box::new(
+        range(a as i64, b as i64).map(|n| (n as u8 as char).to_string()),
+    ))
+}
+
+// `~` (ホームディレクトリ)、`~user` (user のホームディレクトリ)、
+// `~+` (カレントディレクトリ)、`~-` (直前のディレクトリ) を展開する
+// 分からなければそのままにする
+pub fn tilde(user: &str) -> String {
+    let dir = match user {
+        "" => std::env::var("HOME").ok().or_else(|| home_dir(None)),
+        "+" => std::env::current_dir()
+            .ok()
+            .map(|dir| dir.to_string_lossy().into_owned()),
+        "-" => std::env::var("OLDPWD").ok(),
+        user => home_dir(Some(user)),
+    };
+    dir.unwrap_or_else(|| format!("~{}", user))
+}
+
+// パスワードデータベースのホームディレクトリ (user が None なら自分のもの)
+fn home_dir(user: Option<&str>) -> Option<String> {
+    let passwd = match user {
+        Some(user) => {
+            let name = std::ffi::CString::new(user).ok()?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+        None => unsafe { libc::getpwuid(libc::getuid()) },
+    };
+    if passwd.is_null() {
+        return None;
+    }
+    let dir = unsafe { std::ffi::CStr::from_ptr((*passwd).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}